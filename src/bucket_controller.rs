@@ -0,0 +1,1248 @@
+//! # Minio buckets controller
+//!
+//! Makes sure Minio buckets match the desired configuration of MinioBucket objects
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::{Namespace, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::jiff::Timestamp;
+use kube::api::{DeleteParams, Patch, PatchParams};
+use kube::runtime::controller::{self, Action};
+use kube::runtime::events::EventType;
+use kube::runtime::reflector::ObjectRef;
+use kube::runtime::{watcher, Controller};
+use kube::{Api, Client, ResourceExt};
+
+use crate::bucket_secret::{bucket_secret_data, bucket_secret_options, read_bucket_user};
+use crate::conditions::{
+    is_condition_true, set_condition, CONDITION_CREDENTIALS_PROVISIONED,
+    CONDITION_INSTANCE_REACHABLE, CONDITION_POLICY_ATTACHED, CONDITION_READY,
+};
+use crate::constants::{
+    BUCKET_OWNER_TAG, MINIO_BUCKET_FINALIZER, MINIO_BUCKET_RESYNC_INTERVAL, SKIP_CLEANUP_ANNOTATION,
+};
+use crate::controller::{into_reconcile_error, namespaced_api, Context, ReconcileError};
+use crate::crd::{
+    ClusterMinioInstance, DeletionPolicy, DriftPolicy, InstanceKind, MinioBucket,
+    MinioBucketStatus, MinioInstance, RetiredUser,
+};
+use crate::instance_controller::{instance_service, wait_instance_ready};
+use crate::metrics::BUCKET_DRIFT;
+use crate::minio::{BucketPlan, MinioBackend, MinioTimeoutError, MinioUser};
+use crate::minio_error::is_permanent_error;
+use crate::rotation::{
+    expired_users, grace_period, next_rotation_delay, rotation_request, rotation_trigger,
+};
+use crate::secrets::{
+    create_secret, is_managed_secret, read_secret_str, secret_data_equals, secret_up_to_date,
+    update_secret,
+};
+use crate::selectors::namespace_allowed;
+use crate::workloads::restart_workloads;
+
+/// Run the controller of Minio buckets, until the operator is asked to stop
+pub async fn run(ctx: Arc<Context>) {
+    // As the operator places a finalizer on buckets, deletions are received as updates
+    // of the objects
+    let controllers = ctx
+        .watched_apis::<MinioBucket>()
+        .into_iter()
+        .map(|buckets| {
+            let ctx = ctx.clone();
+            Controller::new(buckets, watcher::Config::default())
+                .shutdown_on_signal()
+                .run(reconcile, error_policy, ctx.clone())
+                .for_each(move |res| {
+                    match res {
+                        // The bucket was deleted before a scheduled reconciliation
+                        Err(controller::Error::ObjectNotFound(obj)) => ctx.forget(&obj.to_string()),
+                        Err(e) => log::warn!("Minio bucket reconciliation error: {e}"),
+                        Ok(_) => {}
+                    }
+                    futures::future::ready(())
+                })
+        });
+    futures::future::join_all(controllers).await;
+}
+
+async fn reconcile(b: Arc<MinioBucket>, ctx: Arc<Context>) -> Result<Action, ReconcileError> {
+    if b.metadata.deletion_timestamp.is_some() {
+        cleanup_bucket(&b, &ctx)
+            .await
+            .map_err(into_reconcile_error)?;
+        ctx.forget(&ObjectRef::from_obj(b.as_ref()).to_string());
+        return Ok(Action::await_change());
+    }
+
+    let mut status = b.status.clone().unwrap_or_default();
+    let res = apply_bucket(&b, &ctx, &mut status).await;
+    update_bucket_status(&b, &ctx.client, status, &res).await?;
+    if let Err(e) = &res {
+        let reason = match e.downcast_ref::<ReconcileError>() {
+            Some(ReconcileError::InstanceUnreachable { .. }) => "InstanceUnreachable",
+            Some(ReconcileError::InstanceNotAllowed { .. }) => "InstanceNotAllowed",
+            Some(ReconcileError::InvalidNamespaceSelector { .. }) => "InvalidNamespaceSelector",
+            Some(ReconcileError::SecretNotManaged { .. }) => "SecretNotManaged",
+            Some(ReconcileError::BucketNotOwned { .. }) => "BucketNotOwned",
+            Some(ReconcileError::RestartFailed { .. }) => "RestartFailed",
+            _ if e.is::<MinioTimeoutError>() => "InstanceTimeout",
+            _ => "ReconcileFailed",
+        };
+        ctx.publish_event(b.as_ref(), EventType::Warning, reason, e, "Reconcile")
+            .await;
+    }
+    let next_rotation = res.map_err(into_reconcile_error)?;
+    let requeue = next_rotation.map_or(MINIO_BUCKET_RESYNC_INTERVAL, |d| {
+        d.min(MINIO_BUCKET_RESYNC_INTERVAL)
+    });
+
+    ctx.forget(&ObjectRef::from_obj(b.as_ref()).to_string());
+    Ok(Action::requeue(requeue))
+}
+
+fn error_policy(b: Arc<MinioBucket>, e: &ReconcileError, ctx: Arc<Context>) -> Action {
+    let delay = ctx.record_failure(&ObjectRef::from_obj(b.as_ref()).to_string());
+    match e {
+        ReconcileError::InstanceUnreachable { .. }
+        | ReconcileError::InstanceNotAllowed { .. }
+        | ReconcileError::InvalidNamespaceSelector { .. }
+        | ReconcileError::SecretNotManaged { .. }
+        | ReconcileError::BucketNotOwned { .. }
+        | ReconcileError::RestartFailed { .. } => {
+            log::warn!(
+                "Could not reconcile bucket {}, will retry in {delay:?} : {e}",
+                b.spec.name
+            )
+        }
+        // Retrying soon is pointless if Minio will reject the operation again, the bucket
+        // is reconciled again once it is modified or at the next resync
+        ReconcileError::Other(e) if is_permanent_error(e) => {
+            log::error!(
+                "Failed to reconcile bucket {}, will retry in {MINIO_BUCKET_RESYNC_INTERVAL:?} : {e}",
+                b.spec.name
+            );
+            return Action::requeue(MINIO_BUCKET_RESYNC_INTERVAL);
+        }
+        ReconcileError::Other(_) => log::error!(
+            "Failed to reconcile bucket {}, will retry in {delay:?} : {e}",
+            b.spec.name
+        ),
+    }
+    Action::requeue(delay)
+}
+
+/// Get the name of the policy that grants access to a bucket
+fn bucket_policy_name(b: &MinioBucket) -> String {
+    format!("bucket-{}", b.spec.name)
+}
+
+/// Replace the list of finalizers of a bucket
+async fn set_bucket_finalizers(
+    b: &MinioBucket,
+    client: &Client,
+    finalizers: Vec<String>,
+) -> anyhow::Result<()> {
+    let buckets: Api<MinioBucket> = namespaced_api(client, b);
+
+    // The resource version is included in the patch to make sure we do not override
+    // a concurrent change of the list of finalizers
+    let patch = serde_json::json!({
+        "metadata": {
+            "finalizers": finalizers,
+            "resourceVersion": b.resource_version(),
+        }
+    });
+    buckets
+        .patch(
+            &b.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await?;
+    Ok(())
+}
+
+/// A Minio instance used by a bucket
+struct BucketInstance {
+    minio: Arc<dyn MinioBackend>,
+    /// The URL of the instance
+    endpoint: String,
+}
+
+/// Get a client of the Minio instance of a bucket, or None if the instance does not exist
+async fn get_minio_service(
+    b: &MinioBucket,
+    ctx: &Context,
+) -> anyhow::Result<Option<BucketInstance>> {
+    // Get instance information
+    let (service, probe) = match b.spec.instance_kind {
+        InstanceKind::MinioInstance => {
+            let instances: Api<MinioInstance> = namespaced_api(&ctx.client, b);
+            let instance = match instances.get_opt(&b.spec.instance).await? {
+                Some(i) => i,
+                None => return Ok(None),
+            };
+            (
+                instance_service(&instance, ctx).await?,
+                instance.spec.readiness_probe,
+            )
+        }
+        InstanceKind::ClusterMinioInstance => {
+            let instances: Api<ClusterMinioInstance> = Api::all(ctx.client.clone());
+            let instance = match instances.get_opt(&b.spec.instance).await? {
+                Some(i) => i,
+                None => return Ok(None),
+            };
+
+            // Check if the namespace of the bucket is allowed to use the instance
+            let namespace = b.namespace().unwrap_or_default();
+            let namespaces: Api<Namespace> = Api::all(ctx.client.clone());
+            let allowed = namespace_allowed(&instance.spec, &namespaces.get(&namespace).await?)
+                .map_err(|e| ReconcileError::InvalidNamespaceSelector {
+                    instance: instance.name_any(),
+                    error: e.to_string(),
+                })?;
+            if !allowed {
+                return Err(ReconcileError::InstanceNotAllowed {
+                    instance: instance.name_any(),
+                    namespace,
+                }
+                .into());
+            }
+
+            (
+                instance_service(&instance, ctx).await?,
+                instance.spec.instance.readiness_probe,
+            )
+        }
+    };
+
+    // Check if Minio is responding
+    let minio = service.backend()?;
+    if !wait_instance_ready(minio.as_ref(), &probe).await {
+        return Err(ReconcileError::InstanceUnreachable {
+            instance: b.spec.instance.clone(),
+            attempts: probe.attempts,
+        }
+        .into());
+    }
+
+    Ok(Some(BucketInstance {
+        minio,
+        endpoint: service.hostname,
+    }))
+}
+
+/// Save the outcome of the reconciliation of a bucket in its status, if it changed
+async fn update_bucket_status<T>(
+    b: &MinioBucket,
+    client: &Client,
+    mut status: MinioBucketStatus,
+    res: &anyhow::Result<T>,
+) -> anyhow::Result<()> {
+    set_reconcile_outcome(b, &mut status, res);
+
+    if b.status.as_ref() == Some(&status) {
+        return Ok(());
+    }
+    save_bucket_status(b, client, &status).await
+}
+
+/// Replace the status of a bucket
+async fn save_bucket_status(
+    b: &MinioBucket,
+    client: &Client,
+    status: &MinioBucketStatus,
+) -> anyhow::Result<()> {
+    let buckets: Api<MinioBucket> = namespaced_api(client, b);
+    let patch = serde_json::json!({ "status": status });
+    buckets
+        .patch_status(
+            &b.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Record the outcome of the reconciliation of a bucket in its status. The generation of the
+/// bucket is only observed once it was successfully reconciled
+fn set_reconcile_outcome<T>(
+    b: &MinioBucket,
+    status: &mut MinioBucketStatus,
+    res: &anyhow::Result<T>,
+) {
+    let generation = b.metadata.generation;
+    status.bucket_name = Some(b.spec.name.clone());
+
+    match res {
+        Ok(_) => {
+            status.observed_generation = generation;
+            set_condition(
+                &mut status.conditions,
+                CONDITION_READY,
+                true,
+                "Reconciled",
+                "The bucket matches its desired configuration",
+                generation,
+            );
+            status.last_error = None;
+        }
+        Err(e) => {
+            set_condition(
+                &mut status.conditions,
+                CONDITION_READY,
+                false,
+                "ReconcileFailed",
+                e,
+                generation,
+            );
+            status.last_error = Some(e.to_string());
+        }
+    }
+}
+
+/// Make sure a bucket is compliant with a desired configuration. Returns the delay before the
+/// next rotation of its credentials or removal of one of its previous users, if any
+async fn apply_bucket(
+    b: &MinioBucket,
+    ctx: &Context,
+    status: &mut MinioBucketStatus,
+) -> anyhow::Result<Option<Duration>> {
+    let client = &ctx.client;
+    log::info!("Apply configuration for bucket {}", b.spec.name);
+    let generation = b.metadata.generation;
+
+    // Make sure we will be notified before the bucket is deleted
+    if !b.finalizers().iter().any(|f| f == MINIO_BUCKET_FINALIZER) {
+        let mut finalizers = b.finalizers().to_vec();
+        finalizers.push(MINIO_BUCKET_FINALIZER.to_string());
+        set_bucket_finalizers(b, client, finalizers).await?;
+    }
+
+    let instance = match get_minio_service(b, ctx).await {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            let msg = format!("Minio instance {} does not exist!", b.spec.instance);
+            set_condition(
+                &mut status.conditions,
+                CONDITION_INSTANCE_REACHABLE,
+                false,
+                "InstanceNotFound",
+                &msg,
+                generation,
+            );
+            anyhow::bail!(msg);
+        }
+        Err(e) => {
+            let reason = match e.downcast_ref::<ReconcileError>() {
+                Some(ReconcileError::InstanceUnreachable { .. }) => "InstanceUnreachable",
+                Some(ReconcileError::InstanceNotAllowed { .. }) => "InstanceNotAllowed",
+                Some(ReconcileError::InvalidNamespaceSelector { .. }) => "InvalidNamespaceSelector",
+                _ => "InstanceMisconfigured",
+            };
+            set_condition(
+                &mut status.conditions,
+                CONDITION_INSTANCE_REACHABLE,
+                false,
+                reason,
+                &e,
+                generation,
+            );
+            return Err(e);
+        }
+    };
+    set_condition(
+        &mut status.conditions,
+        CONDITION_INSTANCE_REACHABLE,
+        true,
+        "InstanceReachable",
+        "Minio instance is responding",
+        generation,
+    );
+
+    let minio = instance.minio;
+
+    let (user, secret) = match provision_credentials(b, ctx, status, &instance.endpoint).await {
+        Ok(res) => res,
+        Err(e) => {
+            let reason = match e.downcast_ref::<ReconcileError>() {
+                Some(ReconcileError::SecretNotManaged { .. }) => "SecretNotManaged",
+                _ => "SecretInvalid",
+            };
+            set_condition(
+                &mut status.conditions,
+                CONDITION_CREDENTIALS_PROVISIONED,
+                false,
+                reason,
+                &e,
+                generation,
+            );
+            return Err(e);
+        }
+    };
+    set_condition(
+        &mut status.conditions,
+        CONDITION_CREDENTIALS_PROVISIONED,
+        true,
+        "SecretAvailable",
+        "Bucket credentials are available in the secret",
+        generation,
+    );
+    status.secret_name = Some(b.spec.secret.clone());
+    status.secret_keys = Some(b.spec.secret_keys.clone());
+
+    log::debug!("Create or update bucket...");
+    let (plan, spec_changed) = sync_bucket(b, minio.as_ref(), status).await?;
+    if plan.create || spec_changed {
+        if plan.create {
+            ctx.publish_event(
+                b,
+                EventType::Normal,
+                "BucketCreated",
+                format!("Bucket {} was created", b.spec.name),
+                "CreateBucket",
+            )
+            .await;
+        }
+        for change in &plan.changes {
+            ctx.publish_event(
+                b,
+                EventType::Normal,
+                change.event_reason(),
+                format!("Bucket setting changed: {change}"),
+                "UpdateBucket",
+            )
+            .await;
+        }
+    } else {
+        report_bucket_drift(b, ctx, &plan).await;
+    }
+
+    if let Err(e) = apply_bucket_access(b, minio.as_ref(), &user).await {
+        set_condition(
+            &mut status.conditions,
+            CONDITION_POLICY_ATTACHED,
+            false,
+            "PolicyAttachFailed",
+            &e,
+            generation,
+        );
+        return Err(e);
+    }
+    let was_attached = b
+        .status
+        .as_ref()
+        .is_some_and(|s| is_condition_true(&s.conditions, CONDITION_POLICY_ATTACHED));
+    if !was_attached {
+        ctx.publish_event(
+            b,
+            EventType::Normal,
+            "PolicyAttached",
+            format!(
+                "Policy {} is attached to the user of the bucket",
+                bucket_policy_name(b)
+            ),
+            "AttachPolicy",
+        )
+        .await;
+    }
+    set_condition(
+        &mut status.conditions,
+        CONDITION_POLICY_ATTACHED,
+        true,
+        "PolicyAttached",
+        format!("Policy {} is attached to the user", bucket_policy_name(b)),
+        generation,
+    );
+    remove_superseded_user(b, minio.as_ref(), status, &user).await?;
+
+    let next_rotation = rotate_credentials(
+        b,
+        ctx,
+        minio.as_ref(),
+        status,
+        user,
+        &secret,
+        &instance.endpoint,
+    )
+    .await?;
+    restart_secret_consumers(b, ctx, status).await?;
+
+    log::debug!("Successfully applied desired configuration!");
+
+    Ok(next_rotation)
+}
+
+/// Make a bucket match its desired configuration, and get the differences that were found,
+/// along with whether the desired configuration changed since it was last applied. Differences
+/// found while the desired configuration did not change are drifts, that are only corrected if
+/// the drift policy of the bucket allows it. Buckets managed by another MinioBucket are refused
+async fn sync_bucket(
+    b: &MinioBucket,
+    minio: &dyn MinioBackend,
+    status: &mut MinioBucketStatus,
+) -> anyhow::Result<(BucketPlan, bool)> {
+    let generation = b.metadata.generation;
+    let applied = status.applied_generation.or(status.observed_generation);
+    let spec_changed = applied != generation;
+
+    let owner = bucket_owner(b);
+    let current_owner = current_bucket_owner(b, minio).await?;
+    if let Some(current_owner) = current_owner.as_ref().filter(|o| **o != owner) {
+        return Err(ReconcileError::BucketNotOwned {
+            bucket: b.spec.name.clone(),
+            owner: current_owner.clone(),
+        }
+        .into());
+    }
+
+    let plan = minio.bucket_plan(&b.spec).await?;
+    log::info!("Plan for bucket {}: {plan}", b.spec.name);
+
+    if plan.create || spec_changed {
+        minio.bucket_execute_plan(&b.spec, &plan).await?;
+        status.applied_generation = generation;
+    } else {
+        for drift in &plan.changes {
+            log::warn!(
+                "Bucket {} drifted from its desired configuration: {drift}",
+                b.spec.name
+            );
+
+            if b.spec.drift_policy == DriftPolicy::Correct {
+                minio.bucket_apply_change(&b.spec.name, drift).await?;
+            }
+        }
+    }
+
+    // Buckets created before their owner was recorded are claimed
+    if current_owner.is_none() {
+        log::info!("Bucket {} is now managed by {owner}", b.spec.name);
+        let mut tags = minio.bucket_get_tags(&b.spec.name).await?;
+        tags.insert(BUCKET_OWNER_TAG.to_string(), owner);
+        minio.bucket_set_tags(&b.spec.name, &tags).await?;
+    }
+
+    Ok((plan, spec_changed))
+}
+
+/// Get the identifier of a MinioBucket, stored in the owner tag of its Minio bucket
+fn bucket_owner(b: &MinioBucket) -> String {
+    format!("{}/{}", b.namespace().unwrap_or_default(), b.name_any())
+}
+
+/// Get the MinioBucket that manages the Minio bucket of a MinioBucket, if the bucket exists
+/// and its owner was recorded
+async fn current_bucket_owner(
+    b: &MinioBucket,
+    minio: &dyn MinioBackend,
+) -> anyhow::Result<Option<String>> {
+    if !minio.bucket_exists(&b.spec.name).await? {
+        return Ok(None);
+    }
+    Ok(minio
+        .bucket_get_tags(&b.spec.name)
+        .await?
+        .remove(BUCKET_OWNER_TAG))
+}
+
+/// Report the settings of an existing bucket that were found to differ from its desired
+/// configuration
+async fn report_bucket_drift(b: &MinioBucket, ctx: &Context, plan: &BucketPlan) {
+    let correct = b.spec.drift_policy == DriftPolicy::Correct;
+    for drift in &plan.changes {
+        let (action, reason) = match correct {
+            true => ("corrected", "DriftCorrected"),
+            false => ("reported", "DriftDetected"),
+        };
+        BUCKET_DRIFT
+            .with_label_values(&[
+                b.namespace().unwrap_or_default().as_str(),
+                b.spec.name.as_str(),
+                drift.field(),
+                action,
+            ])
+            .inc();
+
+        ctx.publish_event(
+            b,
+            EventType::Warning,
+            reason,
+            format!("Bucket setting changed outside of the operator: {drift}"),
+            "CheckDrift",
+        )
+        .await;
+    }
+}
+
+/// Get the credentials of the user of a bucket, creating them if required. The secret of the
+/// bucket is updated if its connection information or its settings are outdated, and repaired
+/// if some of its credentials are missing. Secrets that were not created by the operator are
+/// never modified, and are refused. Returns the credentials along with the secret
+async fn provision_credentials(
+    b: &MinioBucket,
+    ctx: &Context,
+    status: &mut MinioBucketStatus,
+    endpoint: &str,
+) -> anyhow::Result<(MinioUser, Secret)> {
+    // Get user key & password
+    let secrets: Api<Secret> = namespaced_api(&ctx.client, b);
+    let options = bucket_secret_options(b);
+    let user_secret = match secrets.get_opt(&b.spec.secret).await? {
+        Some(s) if !is_managed_secret(&s) => {
+            return Err(ReconcileError::SecretNotManaged {
+                secret: b.spec.secret.clone(),
+            }
+            .into());
+        }
+        Some(s) => s,
+        None => {
+            log::info!(
+                "Needs to create the secret {} for the bucket {}",
+                b.spec.secret,
+                b.spec.name
+            );
+
+            // The secret needs to be created
+            let new_user = MinioUser::gen_random(&b.spec.name);
+            let secret = create_secret(
+                &secrets,
+                &b.spec.secret,
+                bucket_secret_data(&b.spec, &new_user, endpoint)?,
+                &options,
+            )
+            .await?;
+            // Fresh credentials fulfill the rotation requested by the bucket, if any
+            status.rotation_request = rotation_request(b).cloned();
+
+            ctx.publish_event(
+                b,
+                EventType::Normal,
+                "SecretGenerated",
+                format!("Credentials were generated in secret {}", b.spec.secret),
+                "CreateSecret",
+            )
+            .await;
+            return Ok((new_user, secret));
+        }
+    };
+
+    let (user, repaired) = read_bucket_user(
+        &b.spec,
+        &user_secret,
+        status.username.as_deref(),
+        status.secret_keys.as_ref(),
+    );
+    let data = bucket_secret_data(&b.spec, &user, endpoint)?;
+    if secret_up_to_date(&user_secret, &data, &options) {
+        return Ok((user, user_secret));
+    }
+
+    log::info!(
+        "Update the secret {} for the bucket {}",
+        b.spec.secret,
+        b.spec.name
+    );
+    // Consumers only need to be restarted when the content of the secret changes
+    let data_changed = !secret_data_equals(&user_secret, &data);
+    let secret = update_secret(&secrets, &user_secret, data, &options).await?;
+
+    let (reason, note) = match repaired {
+        true => (
+            "SecretRepaired",
+            "Missing or invalid credentials were generated again in secret",
+        ),
+        false => (
+            "SecretUpdated",
+            "Connection information and settings were updated in secret",
+        ),
+    };
+    ctx.publish_event(
+        b,
+        EventType::Normal,
+        reason,
+        format!("{note} {}", b.spec.secret),
+        "UpdateSecret",
+    )
+    .await;
+    if data_changed {
+        status.restart_pending = true;
+    }
+
+    Ok((user, secret))
+}
+
+/// Restart the workloads that consume the secret of a bucket, once it was rewritten. The
+/// pending restart is recorded in the status, so that failed restarts are retried by the next
+/// reconciliations until every workload is restarted
+async fn restart_secret_consumers(
+    b: &MinioBucket,
+    ctx: &Context,
+    status: &mut MinioBucketStatus,
+) -> anyhow::Result<()> {
+    if !status.restart_pending {
+        return Ok(());
+    }
+    if b.spec.restart_on_secret_change.is_empty() {
+        status.restart_pending = false;
+        return Ok(());
+    }
+
+    let res = restart_workloads(b, ctx).await;
+    if !res.restarted.is_empty() {
+        ctx.publish_event(
+            b,
+            EventType::Normal,
+            "WorkloadsRestarted",
+            format!(
+                "Restarted {} after secret {} was rewritten",
+                res.restarted.join(", "),
+                b.spec.secret
+            ),
+            "RestartWorkloads",
+        )
+        .await;
+    }
+    if !res.failures.is_empty() {
+        return Err(ReconcileError::RestartFailed {
+            secret: b.spec.secret.clone(),
+            failures: res.failures.join(", "),
+        }
+        .into());
+    }
+
+    status.restart_pending = false;
+    Ok(())
+}
+
+/// Grant a user write access to a bucket
+async fn apply_bucket_access(
+    b: &MinioBucket,
+    minio: &dyn MinioBackend,
+    user: &MinioUser,
+) -> anyhow::Result<()> {
+    let policy_name = bucket_policy_name(b);
+    log::debug!("Create or update policy '{policy_name}'...");
+    let policy_content =
+        include_str!("policy_template.json").replace("{{ bucket }}", b.spec.name.as_str());
+    minio.policy_apply(&policy_name, &policy_content).await?;
+
+    log::debug!("Create or update user '{}'...", user.username);
+    minio.user_apply(user).await?;
+
+    log::debug!("Attach policy '{policy_name}' to user...");
+    minio.policy_attach_user(user, &policy_name).await?;
+
+    Ok(())
+}
+
+/// Replace the user of a bucket with a new one when its credentials must be rotated, and
+/// remove the previous users whose grace period is over. Returns the delay before the next
+/// rotation or removal, if any
+async fn rotate_credentials(
+    b: &MinioBucket,
+    ctx: &Context,
+    minio: &dyn MinioBackend,
+    status: &mut MinioBucketStatus,
+    user: MinioUser,
+    secret: &Secret,
+    endpoint: &str,
+) -> anyhow::Result<Option<Duration>> {
+    let now = Timestamp::now();
+    let credentials_created = secret.creation_timestamp();
+    let mut current = user.username.clone();
+    if let Some(trigger) = rotation_trigger(b, status, credentials_created.as_ref(), now) {
+        log::info!("Rotate the credentials of bucket {} {trigger}", b.spec.name);
+
+        // The previous user is recorded before it is replaced, so that it is removed even if
+        // the status can not be saved once the rotation is done
+        let grace_period = grace_period(b);
+        retire_user(status, user.username, Time(now.checked_add(grace_period)?));
+        save_bucket_status(b, &ctx.client, status).await?;
+
+        let new_user = MinioUser::gen_random(&b.spec.name);
+        if let Err(e) = replace_user(b, ctx, minio, &new_user, secret, endpoint).await {
+            if let Err(e) = minio.user_remove(&new_user.username).await {
+                log::error!(
+                    "Failed to remove the new user {} of bucket {}: {e}",
+                    new_user.username,
+                    b.spec.name
+                );
+            }
+            return Err(e);
+        }
+        status.restart_pending = true;
+
+        current = new_user.username.clone();
+        status.username = Some(new_user.username);
+        status.last_rotation_time = Some(Time(now));
+        status.rotation_request = rotation_request(b).cloned();
+
+        ctx.publish_event(
+            b,
+            EventType::Normal,
+            "CredentialsRotated",
+            format!(
+                "Credentials were rotated {trigger} in secret {}, the previous ones remain valid for {grace_period:?}",
+                b.spec.secret
+            ),
+            "RotateCredentials",
+        )
+        .await;
+    }
+
+    // A user whose replacement failed is still the current user, and must never be removed
+    status.retired_users.retain(|u| u.username != current);
+    let expired: Vec<String> = expired_users(status, now)
+        .into_iter()
+        .map(|u| u.username.clone())
+        .collect();
+    for username in expired {
+        log::info!(
+            "Remove the previous user {username} of bucket {}",
+            b.spec.name
+        );
+        minio.user_remove(&username).await?;
+        status.retired_users.retain(|u| u.username != username);
+    }
+
+    Ok(next_rotation_delay(
+        b,
+        status,
+        credentials_created.as_ref(),
+        now,
+    ))
+}
+
+/// Remove the previous user of a bucket once its secret contains the credentials of another
+/// user, for example when its access key was lost, unless the previous user was retired by a
+/// rotation. The current user is then recorded in the status
+async fn remove_superseded_user(
+    b: &MinioBucket,
+    minio: &dyn MinioBackend,
+    status: &mut MinioBucketStatus,
+    user: &MinioUser,
+) -> anyhow::Result<()> {
+    if let Some(previous) = status.username.as_ref().filter(|u| **u != user.username) {
+        if !status.retired_users.iter().any(|u| u.username == *previous) {
+            log::info!(
+                "Remove the user {previous} of bucket {}, superseded by {}",
+                b.spec.name,
+                user.username
+            );
+            minio.user_remove(previous).await?;
+        }
+    }
+    status.username = Some(user.username.clone());
+    Ok(())
+}
+
+/// Record a previous user of a bucket, that expires at a given time
+fn retire_user(status: &mut MinioBucketStatus, username: String, expiration_time: Time) {
+    status.retired_users.retain(|u| u.username != username);
+    status.retired_users.push(RetiredUser {
+        username,
+        expiration_time,
+    });
+}
+
+/// Grant a new user access to a bucket, and write its credentials in the secret of the bucket
+async fn replace_user(
+    b: &MinioBucket,
+    ctx: &Context,
+    minio: &dyn MinioBackend,
+    new_user: &MinioUser,
+    secret: &Secret,
+    endpoint: &str,
+) -> anyhow::Result<()> {
+    apply_bucket_access(b, minio, new_user).await?;
+
+    let secrets: Api<Secret> = namespaced_api(&ctx.client, b);
+    update_secret(
+        &secrets,
+        secret,
+        bucket_secret_data(&b.spec, new_user, endpoint)?,
+        &bucket_secret_options(b),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Remove the resources of a deleted bucket, according to its deletion policy, and then
+/// release its finalizer
+async fn cleanup_bucket(b: &MinioBucket, ctx: &Context) -> anyhow::Result<()> {
+    let client = &ctx.client;
+    if !b.finalizers().iter().any(|f| f == MINIO_BUCKET_FINALIZER) {
+        return Ok(());
+    }
+
+    log::info!(
+        "Clean up bucket {} with deletion policy {:?}",
+        b.spec.name,
+        b.spec.deletion_policy
+    );
+
+    let skip_cleanup = b
+        .annotations()
+        .get(SKIP_CLEANUP_ANNOTATION)
+        .is_some_and(|v| v == "true");
+    if skip_cleanup {
+        log::warn!(
+            "Skip the clean up of bucket {}, as requested by its {SKIP_CLEANUP_ANNOTATION} annotation",
+            b.spec.name
+        );
+    } else if b.spec.deletion_policy != DeletionPolicy::Retain {
+        match get_minio_service(b, ctx).await? {
+            None => log::warn!(
+                "Minio instance {} does not exist anymore, skipping clean up of bucket {}",
+                b.spec.instance,
+                b.spec.name
+            ),
+            Some(instance) => {
+                let secrets: Api<Secret> = namespaced_api(client, b);
+                let secret = secrets.get_opt(&b.spec.secret).await?;
+                // The operator does not manage the users of secrets it did not create, and can
+                // not find the user of a secret whose access key is missing
+                let mut usernames: Vec<String> = secret
+                    .as_ref()
+                    .filter(|s| is_managed_secret(s))
+                    .and_then(|s| read_secret_str(s, &b.spec.secret_keys.access_key).ok())
+                    .into_iter()
+                    .collect();
+                // The user recorded in the status may differ from the one of the secret, and
+                // the previous users of the bucket are still valid during their grace period
+                if let Some(status) = &b.status {
+                    usernames.extend(status.username.clone());
+                    usernames.extend(status.retired_users.iter().map(|u| u.username.clone()));
+                }
+                usernames.sort();
+                usernames.dedup();
+
+                remove_bucket_resources(b, instance.minio.as_ref(), &usernames).await?;
+
+                if secret.as_ref().is_some_and(is_managed_secret) {
+                    log::debug!("Remove secret '{}'...", b.spec.secret);
+                    secrets
+                        .delete(&b.spec.secret, &DeleteParams::default())
+                        .await?;
+                }
+            }
+        }
+    }
+
+    let finalizers = b
+        .finalizers()
+        .iter()
+        .filter(|f| *f != MINIO_BUCKET_FINALIZER)
+        .cloned()
+        .collect();
+    set_bucket_finalizers(b, client, finalizers).await?;
+
+    log::debug!("Successfully cleaned up bucket!");
+
+    Ok(())
+}
+
+/// Remove the users, the policy and, according to the deletion policy, the Minio bucket of
+/// a deleted bucket. The policy and the bucket are kept if another MinioBucket manages them
+async fn remove_bucket_resources(
+    b: &MinioBucket,
+    minio: &dyn MinioBackend,
+    usernames: &[String],
+) -> anyhow::Result<()> {
+    for username in usernames {
+        log::debug!("Remove user '{username}'...");
+        minio.user_remove(username).await?;
+    }
+
+    let owner = current_bucket_owner(b, minio).await?;
+    if let Some(owner) = owner.filter(|o| *o != bucket_owner(b)) {
+        log::warn!(
+            "Bucket {} is managed by MinioBucket {owner}, it will not be removed",
+            b.spec.name
+        );
+        return Ok(());
+    }
+
+    let policy_name = bucket_policy_name(b);
+    log::debug!("Remove policy '{policy_name}'...");
+    minio.policy_remove(&policy_name).await?;
+
+    match b.spec.deletion_policy {
+        DeletionPolicy::DeleteIfEmpty => {
+            if minio.bucket_is_empty(&b.spec.name).await? {
+                log::debug!("Remove empty bucket...");
+                minio.bucket_remove(&b.spec.name, false).await?;
+            } else {
+                log::warn!(
+                    "Bucket {} is not empty, it will not be removed",
+                    b.spec.name
+                );
+            }
+        }
+        DeletionPolicy::Delete => {
+            log::debug!("Remove bucket and its content...");
+            minio.bucket_remove(&b.spec.name, true).await?;
+        }
+        DeletionPolicy::Retain | DeletionPolicy::DeleteUserOnly => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use k8s_openapi::jiff::Timestamp;
+
+    use crate::bucket_controller::{
+        apply_bucket_access, bucket_policy_name, remove_bucket_resources, remove_superseded_user,
+        retire_user, set_reconcile_outcome, sync_bucket,
+    };
+    use crate::constants::BUCKET_OWNER_TAG;
+    use crate::controller::ReconcileError;
+    use crate::crd::{
+        DeletionPolicy, DriftPolicy, MinioBucket, MinioBucketSpec, MinioBucketStatus,
+    };
+    use crate::minio::{MinioBackend, MinioUser};
+    use crate::minio_fake::FakeMinio;
+
+    fn test_bucket(spec: MinioBucketSpec) -> MinioBucket {
+        let mut b = MinioBucket::new(
+            "bucket",
+            MinioBucketSpec {
+                name: "bucket".to_string(),
+                secret: "bucket-secret".to_string(),
+                ..spec
+            },
+        );
+        b.metadata.generation = Some(1);
+        b
+    }
+
+    #[tokio::test]
+    async fn sync_creates_bucket() {
+        let minio = FakeMinio::new();
+        let b = test_bucket(MinioBucketSpec {
+            versioning: true,
+            quota: Some(1000),
+            ..Default::default()
+        });
+
+        let mut status = MinioBucketStatus::default();
+        let (plan, _) = sync_bucket(&b, &minio, &mut status).await.unwrap();
+        assert!(plan.create);
+        assert_eq!(status.applied_generation, Some(1));
+        let settings = minio.bucket("bucket").unwrap().settings;
+        assert!(settings.versioning);
+        assert_eq!(settings.quota, Some(1000));
+
+        let (plan, spec_changed) = sync_bucket(&b, &minio, &mut status).await.unwrap();
+        assert!(plan.is_empty());
+        assert!(!spec_changed);
+    }
+
+    #[tokio::test]
+    async fn sync_corrects_or_reports_drift() {
+        let minio = FakeMinio::new();
+        let mut b = test_bucket(MinioBucketSpec {
+            drift_policy: DriftPolicy::Report,
+            ..Default::default()
+        });
+        let mut status = MinioBucketStatus::default();
+        sync_bucket(&b, &minio, &mut status).await.unwrap();
+
+        minio.bucket_set_quota("bucket", Some(10)).await.unwrap();
+        let (plan, spec_changed) = sync_bucket(&b, &minio, &mut status).await.unwrap();
+        assert!(!spec_changed);
+        assert_eq!(plan.changes.len(), 1);
+        assert_eq!(minio.bucket("bucket").unwrap().settings.quota, Some(10));
+
+        b.spec.drift_policy = DriftPolicy::Correct;
+        let (plan, _) = sync_bucket(&b, &minio, &mut status).await.unwrap();
+        assert_eq!(plan.changes.len(), 1);
+        assert_eq!(minio.bucket("bucket").unwrap().settings.quota, None);
+    }
+
+    #[tokio::test]
+    async fn refuse_bucket_of_other_namespace() {
+        let minio = FakeMinio::new();
+        let mut b = test_bucket(MinioBucketSpec {
+            deletion_policy: DeletionPolicy::Delete,
+            ..Default::default()
+        });
+        b.metadata.namespace = Some("team-a".to_string());
+        sync_bucket(&b, &minio, &mut MinioBucketStatus::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            minio.bucket("bucket").unwrap().tags[BUCKET_OWNER_TAG],
+            "team-a/bucket"
+        );
+        apply_bucket_access(&b, &minio, &MinioUser::gen_random("bucket"))
+            .await
+            .unwrap();
+
+        let mut other = b.clone();
+        other.metadata.namespace = Some("team-b".to_string());
+        other.spec.quota = Some(10);
+        let err = sync_bucket(&other, &minio, &mut MinioBucketStatus::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ReconcileError>(),
+            Some(ReconcileError::BucketNotOwned { .. })
+        ));
+        assert_eq!(minio.bucket("bucket").unwrap().settings.quota, None);
+
+        remove_bucket_resources(&other, &minio, &[]).await.unwrap();
+        assert!(minio.bucket("bucket").is_some());
+        assert_eq!(
+            minio.policy_list().await.unwrap(),
+            vec![bucket_policy_name(&b)]
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_failed_spec_change() {
+        let minio = FakeMinio::new();
+        let mut b = test_bucket(MinioBucketSpec {
+            drift_policy: DriftPolicy::Report,
+            ..Default::default()
+        });
+        let mut status = MinioBucketStatus::default();
+        sync_bucket(&b, &minio, &mut status).await.unwrap();
+
+        // A spec change that could not be applied is not mistaken for a drift afterwards
+        b.metadata.generation = Some(2);
+        b.spec.quota = Some(1000);
+        minio.fail_on("bucket_set_quota", true);
+        assert!(sync_bucket(&b, &minio, &mut status).await.is_err());
+        assert_eq!(status.applied_generation, Some(1));
+
+        minio.fail_on("bucket_set_quota", false);
+        let (_, spec_changed) = sync_bucket(&b, &minio, &mut status).await.unwrap();
+        assert!(spec_changed);
+        assert_eq!(status.applied_generation, Some(2));
+        assert_eq!(minio.bucket("bucket").unwrap().settings.quota, Some(1000));
+    }
+
+    #[test]
+    fn observe_generation_on_success() {
+        let mut b = test_bucket(Default::default());
+        b.metadata.generation = Some(2);
+        let mut status = MinioBucketStatus {
+            observed_generation: Some(1),
+            ..Default::default()
+        };
+
+        set_reconcile_outcome::<()>(&b, &mut status, &Err(anyhow::anyhow!("failed")));
+        assert_eq!(status.observed_generation, Some(1));
+        assert_eq!(status.last_error.as_deref(), Some("failed"));
+
+        set_reconcile_outcome(&b, &mut status, &Ok(()));
+        assert_eq!(status.observed_generation, Some(2));
+        assert_eq!(status.last_error, None);
+    }
+
+    #[test]
+    fn retire_user_once() {
+        let mut status = MinioBucketStatus::default();
+        let at = |secs| Time(Timestamp::from_second(secs).unwrap());
+        retire_user(&mut status, "old".to_string(), at(100));
+        retire_user(&mut status, "old".to_string(), at(200));
+        assert_eq!(status.retired_users.len(), 1);
+        assert_eq!(status.retired_users[0].expiration_time, at(200));
+    }
+
+    #[tokio::test]
+    async fn remove_superseded_users() {
+        let minio = FakeMinio::new();
+        let b = test_bucket(Default::default());
+        let lost = MinioUser::gen_random("bucket");
+        let retired = MinioUser::gen_random("bucket");
+        let user = MinioUser::gen_random("bucket");
+        for u in [&lost, &retired, &user] {
+            minio.user_apply(u).await.unwrap();
+        }
+
+        let mut status = MinioBucketStatus {
+            username: Some(lost.username.clone()),
+            ..Default::default()
+        };
+        remove_superseded_user(&b, &minio, &mut status, &user)
+            .await
+            .unwrap();
+        assert_eq!(status.username.as_ref(), Some(&user.username));
+        assert!(!minio.user_list().await.unwrap().contains(&lost.username));
+
+        retire_user(&mut status, user.username.clone(), Time(Timestamp::now()));
+        remove_superseded_user(&b, &minio, &mut status, &retired)
+            .await
+            .unwrap();
+        assert!(minio.user_list().await.unwrap().contains(&user.username));
+    }
+
+    #[tokio::test]
+    async fn grant_bucket_access() {
+        let minio = FakeMinio::new();
+        let b = test_bucket(Default::default());
+        let user = MinioUser::gen_random("bucket");
+
+        minio.fail_on("policy_attach_user", true);
+        assert!(apply_bucket_access(&b, &minio, &user).await.is_err());
+        minio.fail_on("policy_attach_user", false);
+
+        for _ in 0..2 {
+            apply_bucket_access(&b, &minio, &user).await.unwrap();
+            assert_eq!(
+                minio.policy_attach_get_user_list(&user).await.unwrap(),
+                vec![bucket_policy_name(&b)]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn deletion_policies() {
+        for (policy, objects, removed) in [
+            (DeletionPolicy::DeleteUserOnly, 0, false),
+            (DeletionPolicy::DeleteIfEmpty, 1, false),
+            (DeletionPolicy::DeleteIfEmpty, 0, true),
+            (DeletionPolicy::Delete, 1, true),
+        ] {
+            let minio = FakeMinio::new();
+            let b = test_bucket(MinioBucketSpec {
+                deletion_policy: policy,
+                ..Default::default()
+            });
+            // The previous user of the bucket is still valid after a rotation
+            let user = MinioUser::gen_random("bucket");
+            let retired = MinioUser::gen_random("bucket");
+            sync_bucket(&b, &minio, &mut MinioBucketStatus::default())
+                .await
+                .unwrap();
+            apply_bucket_access(&b, &minio, &user).await.unwrap();
+            apply_bucket_access(&b, &minio, &retired).await.unwrap();
+            minio
+                .state
+                .lock()
+                .unwrap()
+                .buckets
+                .get_mut("bucket")
+                .unwrap()
+                .objects = objects;
+
+            remove_bucket_resources(&b, &minio, &[user.username, retired.username])
+                .await
+                .unwrap();
+            assert!(minio.user_list().await.unwrap().is_empty());
+            assert!(minio.policy_list().await.unwrap().is_empty());
+            assert_eq!(minio.bucket("bucket").is_none(), removed, "{policy:?}");
+        }
+    }
+}
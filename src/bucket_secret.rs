@@ -0,0 +1,450 @@
+//! # Bucket secrets
+//!
+//! Content of the secrets that contain the credentials of buckets, with the connection
+//! information selected by their `secretFormat` and the keys rendered from their
+//! `secretTemplate`
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::Resource;
+use reqwest::Url;
+
+use crate::constants::MINIO_REGION;
+use crate::crd::{CredentialsKeys, MinioBucket, MinioBucketSpec, SecretFormat};
+use crate::minio::MinioUser;
+use crate::secrets::{read_secret_str, SecretOptions};
+
+/// The name of the alias or of the remote of the instance in client configuration files
+const CLIENT_CONFIG_ALIAS: &str = "minio";
+
+#[derive(thiserror::Error, Debug)]
+enum BucketSecretError {
+    #[error("Invalid Minio endpoint: {0}")]
+    InvalidEndpoint(String),
+    #[error("Unknown variable '{1}' in the template of secret key '{0}'!")]
+    UnknownVariable(String, String),
+    #[error("Unterminated variable in the template of secret key '{0}'!")]
+    UnterminatedVariable(String),
+    #[error(
+        "Template of secret key '{0}' conflicts with a key of the credentials or of secretFormat!"
+    )]
+    ConflictingKey(String),
+}
+
+/// Get the content of the secret of a bucket, for a user and the endpoint of its instance
+pub fn bucket_secret_data(
+    spec: &MinioBucketSpec,
+    user: &MinioUser,
+    endpoint: &str,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let mut data = BTreeMap::from([
+        (spec.secret_keys.access_key.clone(), user.username.clone()),
+        (spec.secret_keys.secret_key.clone(), user.password.clone()),
+    ]);
+
+    for format in &spec.secret_format {
+        match format {
+            SecretFormat::Endpoint => {
+                data.insert("endpoint".to_string(), endpoint.to_string());
+            }
+            SecretFormat::Bucket => {
+                data.insert("bucket".to_string(), spec.name.clone());
+            }
+            SecretFormat::Region => {
+                data.insert("region".to_string(), MINIO_REGION.to_string());
+            }
+            SecretFormat::AwsEnv => {
+                data.extend([
+                    ("AWS_ACCESS_KEY_ID".to_string(), user.username.clone()),
+                    ("AWS_SECRET_ACCESS_KEY".to_string(), user.password.clone()),
+                    ("AWS_ENDPOINT_URL".to_string(), endpoint.to_string()),
+                    ("AWS_REGION".to_string(), MINIO_REGION.to_string()),
+                ]);
+            }
+            SecretFormat::Rclone => {
+                data.insert("rclone.conf".to_string(), rclone_config(user, endpoint));
+            }
+            SecretFormat::S3cmd => {
+                data.insert(".s3cfg".to_string(), s3cmd_config(user, endpoint)?);
+            }
+            SecretFormat::Mc => {
+                data.insert("config.json".to_string(), mc_config(user, endpoint)?);
+            }
+        }
+    }
+
+    for (key, template) in &spec.secret_template {
+        if data.contains_key(key) {
+            return Err(BucketSecretError::ConflictingKey(key.clone()).into());
+        }
+        let value = render_template(key, template, |var| match var {
+            "accessKey" => Some(user.username.as_str()),
+            "secretKey" => Some(user.password.as_str()),
+            "endpoint" => Some(endpoint),
+            "bucket" => Some(spec.name.as_str()),
+            "region" => Some(MINIO_REGION),
+            _ => None,
+        })?;
+        data.insert(key.clone(), value);
+    }
+
+    Ok(data)
+}
+
+/// Read the credentials stored in the managed secret of a bucket. When `secretKeys` was
+/// renamed, the credentials are read from the keys where they were previously written, or from
+/// the default keys, so that they are moved to the new keys instead of being replaced. The
+/// values that are missing or invalid are generated again, in which case the second returned
+/// value is true. A missing access key is replaced with the known user of the bucket, if any,
+/// so that it is not orphaned
+pub fn read_bucket_user(
+    spec: &MinioBucketSpec,
+    secret: &Secret,
+    known_username: Option<&str>,
+    previous_keys: Option<&CredentialsKeys>,
+) -> (MinioUser, bool) {
+    let read_keys = |keys: &CredentialsKeys| {
+        (
+            read_secret_str(secret, &keys.access_key).ok(),
+            read_secret_str(secret, &keys.secret_key).ok(),
+        )
+    };
+
+    let (username, password) = read_keys(&spec.secret_keys);
+    if let (Some(username), Some(password)) = (&username, &password) {
+        return (
+            MinioUser {
+                username: username.clone(),
+                password: password.clone(),
+            },
+            false,
+        );
+    }
+
+    let default_keys = CredentialsKeys::default();
+    let moved = previous_keys
+        .into_iter()
+        .chain([&default_keys])
+        .filter(|k| **k != spec.secret_keys)
+        .find_map(|k| match read_keys(k) {
+            (Some(username), Some(password)) => Some(MinioUser { username, password }),
+            _ => None,
+        });
+    if let Some(user) = moved {
+        return (user, false);
+    }
+
+    let generated = MinioUser::gen_random(&spec.name);
+    let user = MinioUser {
+        username: username
+            .or_else(|| known_username.map(str::to_string))
+            .unwrap_or(generated.username),
+        password: password.unwrap_or(generated.password),
+    };
+    (user, true)
+}
+
+/// Get the settings of the secret of a bucket
+pub fn bucket_secret_options(b: &MinioBucket) -> SecretOptions {
+    SecretOptions {
+        labels: b.spec.secret_labels.clone(),
+        annotations: b.spec.secret_annotations.clone(),
+        type_: b.spec.secret_type.clone(),
+        immutable: b.spec.secret_immutable,
+        owner: b
+            .spec
+            .secret_owned()
+            .then(|| b.controller_owner_ref(&()))
+            .flatten(),
+    }
+}
+
+/// Replace the `{{variable}}` placeholders of the template of a secret key
+fn render_template<'a>(
+    key: &str,
+    template: &str,
+    vars: impl Fn(&str) -> Option<&'a str>,
+) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| BucketSecretError::UnterminatedVariable(key.to_string()))?;
+        let var = rest[start + 2..start + end].trim();
+        out.push_str(
+            vars(var)
+                .ok_or_else(|| BucketSecretError::UnknownVariable(key.to_string(), var.into()))?,
+        );
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn rclone_config(user: &MinioUser, endpoint: &str) -> String {
+    format!(
+        "[{CLIENT_CONFIG_ALIAS}]
+type = s3
+provider = Minio
+access_key_id = {}
+secret_access_key = {}
+endpoint = {endpoint}
+region = {MINIO_REGION}
+",
+        user.username, user.password
+    )
+}
+
+fn s3cmd_config(user: &MinioUser, endpoint: &str) -> anyhow::Result<String> {
+    let url =
+        Url::parse(endpoint).map_err(|_| BucketSecretError::InvalidEndpoint(endpoint.into()))?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(BucketSecretError::InvalidEndpoint(endpoint.into()).into()),
+    };
+    let use_https = match url.scheme() {
+        "https" => "True",
+        _ => "False",
+    };
+
+    // Minio buckets are reached through path-style requests, hence host_bucket does not
+    // contain the name of the bucket
+    Ok(format!(
+        "[default]
+access_key = {}
+secret_key = {}
+host_base = {host}
+host_bucket = {host}
+bucket_location = {MINIO_REGION}
+use_https = {use_https}
+",
+        user.username, user.password
+    ))
+}
+
+fn mc_config(user: &MinioUser, endpoint: &str) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "version": "10",
+        "aliases": {
+            CLIENT_CONFIG_ALIAS: {
+                "url": endpoint,
+                "accessKey": user.username,
+                "secretKey": user.password,
+                "api": "S3v4",
+                "path": "auto",
+            }
+        }
+    }))?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::ByteString;
+
+    use crate::bucket_secret::{bucket_secret_data, bucket_secret_options, read_bucket_user};
+    use crate::crd::{CredentialsKeys, DeletionPolicy, MinioBucket, MinioBucketSpec, SecretFormat};
+    use crate::minio::MinioUser;
+
+    fn test_user() -> MinioUser {
+        MinioUser {
+            username: "user".to_string(),
+            password: "password".to_string(),
+        }
+    }
+
+    #[test]
+    fn credentials_only() {
+        let spec = MinioBucketSpec {
+            secret_keys: CredentialsKeys {
+                access_key: "AWS_ACCESS_KEY_ID".to_string(),
+                secret_key: "AWS_SECRET_ACCESS_KEY".to_string(),
+            },
+            ..Default::default()
+        };
+
+        let data = bucket_secret_data(&spec, &test_user(), "https://minio.example.com").unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data["AWS_ACCESS_KEY_ID"], "user");
+        assert_eq!(data["AWS_SECRET_ACCESS_KEY"], "password");
+    }
+
+    #[test]
+    fn all_formats() {
+        let spec = MinioBucketSpec {
+            name: "mybucket".to_string(),
+            secret_format: vec![
+                SecretFormat::Endpoint,
+                SecretFormat::Bucket,
+                SecretFormat::Region,
+                SecretFormat::AwsEnv,
+                SecretFormat::Rclone,
+                SecretFormat::S3cmd,
+                SecretFormat::Mc,
+            ],
+            ..Default::default()
+        };
+
+        let data =
+            bucket_secret_data(&spec, &test_user(), "http://minio.storage.svc:9000/").unwrap();
+        assert_eq!(data["accessKey"], "user");
+        assert_eq!(data["endpoint"], "http://minio.storage.svc:9000");
+        assert_eq!(data["bucket"], "mybucket");
+        assert_eq!(data["region"], "us-east-1");
+        assert_eq!(data["AWS_ENDPOINT_URL"], "http://minio.storage.svc:9000");
+        assert!(data["rclone.conf"].contains("endpoint = http://minio.storage.svc:9000\n"));
+        assert!(data[".s3cfg"].contains("host_base = minio.storage.svc:9000\n"));
+        assert!(data[".s3cfg"].contains("use_https = False\n"));
+
+        let mc: serde_json::Value = serde_json::from_str(&data["config.json"]).unwrap();
+        assert_eq!(mc["aliases"]["minio"]["secretKey"], "password");
+    }
+
+    #[test]
+    fn render_templates() {
+        let mut spec = MinioBucketSpec {
+            name: "mybucket".to_string(),
+            secret_template: BTreeMap::from([(
+                "DATABASE_BACKUP_URL".to_string(),
+                "s3://{{accessKey}}:{{ secretKey }}@{{endpoint}}/{{bucket}}".to_string(),
+            )]),
+            ..Default::default()
+        };
+
+        let data = bucket_secret_data(&spec, &test_user(), "minio.example.com").unwrap();
+        assert_eq!(
+            data["DATABASE_BACKUP_URL"],
+            "s3://user:password@minio.example.com/mybucket"
+        );
+
+        spec.secret_template
+            .insert("other".to_string(), "{{ unknown }}".to_string());
+        assert!(bucket_secret_data(&spec, &test_user(), "minio.example.com").is_err());
+
+        spec.secret_template
+            .insert("other".to_string(), "{{ bucket".to_string());
+        assert!(bucket_secret_data(&spec, &test_user(), "minio.example.com").is_err());
+
+        // Templates can not override the credentials nor the keys of secretFormat
+        spec.secret_template.remove("other");
+        spec.secret_template
+            .insert("accessKey".to_string(), "{{ bucket }}".to_string());
+        assert!(bucket_secret_data(&spec, &test_user(), "minio.example.com").is_err());
+
+        spec.secret_template.remove("accessKey");
+        spec.secret_format = vec![SecretFormat::Endpoint];
+        spec.secret_template
+            .insert("endpoint".to_string(), "{{ endpoint }}".to_string());
+        assert!(bucket_secret_data(&spec, &test_user(), "minio.example.com").is_err());
+    }
+
+    #[test]
+    fn secret_owner() {
+        let mut b = MinioBucket::new("bucket", Default::default());
+        b.metadata.uid = Some("uid".to_string());
+        assert!(bucket_secret_options(&b).owner.is_none());
+
+        b.spec.deletion_policy = DeletionPolicy::Delete;
+        let owner = bucket_secret_options(&b).owner.unwrap();
+        assert_eq!(owner.kind, "MinioBucket");
+        assert_eq!(owner.controller, Some(true));
+
+        b.spec.secret_owner_reference = Some(false);
+        assert!(bucket_secret_options(&b).owner.is_none());
+    }
+
+    #[test]
+    fn repair_secret() {
+        let spec = MinioBucketSpec {
+            name: "bucket".to_string(),
+            ..Default::default()
+        };
+        let mut secret = Secret {
+            data: Some(BTreeMap::from([
+                ("accessKey".to_string(), ByteString(b"user".to_vec())),
+                ("secretKey".to_string(), ByteString(b"password".to_vec())),
+            ])),
+            ..Default::default()
+        };
+        assert_eq!(
+            read_bucket_user(&spec, &secret, Some("other"), None),
+            (test_user(), false)
+        );
+
+        let data = secret.data.as_mut().unwrap();
+        data.insert("secretKey".to_string(), ByteString(vec![0xff, 0xfe]));
+        let (user, repaired) = read_bucket_user(&spec, &secret, None, None);
+        assert!(repaired);
+        assert_eq!(user.username, "user");
+        assert_ne!(user.password, "password");
+
+        secret.data = None;
+        let (user, repaired) = read_bucket_user(&spec, &secret, None, None);
+        assert!(repaired);
+        assert!(user.username.starts_with("bucket_"));
+
+        // The known user of the bucket is kept when its access key was lost
+        let (user, repaired) = read_bucket_user(&spec, &secret, Some("user"), None);
+        assert!(repaired);
+        assert_eq!(user.username, "user");
+    }
+
+    #[test]
+    fn rename_secret_keys() {
+        let previous = CredentialsKeys {
+            access_key: "ACCESS".to_string(),
+            secret_key: "SECRET".to_string(),
+        };
+        let mut spec = MinioBucketSpec {
+            name: "bucket".to_string(),
+            secret_keys: CredentialsKeys {
+                access_key: "AWS_ACCESS_KEY_ID".to_string(),
+                secret_key: "AWS_SECRET_ACCESS_KEY".to_string(),
+            },
+            ..Default::default()
+        };
+        let mut secret = Secret {
+            data: Some(BTreeMap::from([
+                ("ACCESS".to_string(), ByteString(b"user".to_vec())),
+                ("SECRET".to_string(), ByteString(b"password".to_vec())),
+            ])),
+            ..Default::default()
+        };
+
+        // The credentials are moved from the previous keys
+        assert_eq!(
+            read_bucket_user(&spec, &secret, Some("user"), Some(&previous)),
+            (test_user(), false)
+        );
+        let data = bucket_secret_data(&spec, &test_user(), "minio.example.com").unwrap();
+        assert_eq!(data["AWS_SECRET_ACCESS_KEY"], "password");
+        assert!(!data.contains_key("SECRET"));
+
+        // Or from the default keys, when the previous keys are not known
+        secret.data = Some(BTreeMap::from([
+            ("accessKey".to_string(), ByteString(b"user".to_vec())),
+            ("secretKey".to_string(), ByteString(b"password".to_vec())),
+        ]));
+        assert_eq!(
+            read_bucket_user(&spec, &secret, Some("user"), None),
+            (test_user(), false)
+        );
+
+        // The keys of the spec take precedence
+        spec.secret_keys = CredentialsKeys::default();
+        let data = secret.data.as_mut().unwrap();
+        data.insert("ACCESS".to_string(), ByteString(b"other".to_vec()));
+        data.insert("SECRET".to_string(), ByteString(b"other".to_vec()));
+        assert_eq!(
+            read_bucket_user(&spec, &secret, Some("user"), Some(&previous)),
+            (test_user(), false)
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! # Status conditions helpers
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use k8s_openapi::jiff::Timestamp;
+
+pub const CONDITION_READY: &str = "Ready";
+pub const CONDITION_INSTANCE_REACHABLE: &str = "InstanceReachable";
+pub const CONDITION_CREDENTIALS_PROVISIONED: &str = "CredentialsProvisioned";
+pub const CONDITION_POLICY_ATTACHED: &str = "PolicyAttached";
+pub const CONDITION_REACHABLE: &str = "Reachable";
+
+/// Insert or update a condition in a list of conditions. The last transition time of
+/// the condition is only updated if its status changed
+pub fn set_condition(
+    conditions: &mut Vec<Condition>,
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: impl ToString,
+    observed_generation: Option<i64>,
+) {
+    let status = match status {
+        true => "True",
+        false => "False",
+    };
+
+    let mut condition = Condition {
+        last_transition_time: Time(Timestamp::now()),
+        message: message.to_string(),
+        observed_generation,
+        reason: reason.to_string(),
+        status: status.to_string(),
+        type_: type_.to_string(),
+    };
+
+    match conditions.iter_mut().find(|c| c.type_ == type_) {
+        Some(c) => {
+            if c.status == condition.status {
+                condition.last_transition_time = c.last_transition_time.clone();
+            }
+            *c = condition;
+        }
+        None => conditions.push(condition),
+    }
+}
+
+/// Check if a condition is present and has a `True` status
+pub fn is_condition_true(conditions: &[Condition], type_: &str) -> bool {
+    conditions
+        .iter()
+        .any(|c| c.type_ == type_ && c.status == "True")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::conditions::{is_condition_true, set_condition, CONDITION_READY};
+
+    #[test]
+    fn set_new_condition() {
+        let mut conditions = vec![];
+        set_condition(&mut conditions, CONDITION_READY, true, "Ok", "", Some(1));
+        assert_eq!(conditions.len(), 1);
+        assert!(is_condition_true(&conditions, CONDITION_READY));
+        assert_eq!(conditions[0].observed_generation, Some(1));
+    }
+
+    #[test]
+    fn update_condition_keeps_transition_time() {
+        let mut conditions = vec![];
+        set_condition(&mut conditions, CONDITION_READY, false, "Err", "a", Some(1));
+        let time = conditions[0].last_transition_time.clone();
+
+        set_condition(&mut conditions, CONDITION_READY, false, "Err", "b", Some(2));
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(conditions[0].last_transition_time, time);
+        assert_eq!(conditions[0].message, "b");
+
+        set_condition(&mut conditions, CONDITION_READY, true, "Ok", "", Some(2));
+        assert!(is_condition_true(&conditions, CONDITION_READY));
+    }
+}
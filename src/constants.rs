@@ -1,11 +1,72 @@
 //! # Application constants
-pub const SECRET_MINIO_INSTANCE_ACCESS_KEY: &str = "accessKey";
-pub const SECRET_MINIO_INSTANCE_SECRET_KEY: &str = "secretKey";
 
-pub const SECRET_MINIO_BUCKET_ACCESS_KEY: &str = "accessKey";
-pub const SECRET_MINIO_BUCKET_SECRET_KEY: &str = "secretKey";
+use std::time::Duration;
+
+/// The name of the operator, as it appears in the Kubernetes events it publishes
+pub const OPERATOR_NAME: &str = "minio-operator";
+
+/// The default address of the metrics endpoint, that can be overridden with the
+/// `METRICS_ADDR` environment variable
+pub const DEFAULT_METRICS_ADDR: &str = "0.0.0.0:9090";
+
+/// The default keys of the access key and of the secret key in credentials secrets, used
+/// unless the instance or the bucket overrides them
+pub const SECRET_DEFAULT_ACCESS_KEY: &str = "accessKey";
+pub const SECRET_DEFAULT_SECRET_KEY: &str = "secretKey";
+
+/// The keys of the certificate and the private key in secrets of type `kubernetes.io/tls`
+pub const SECRET_TLS_CERT: &str = "tls.crt";
+pub const SECRET_TLS_KEY: &str = "tls.key";
 
 pub const SECRET_MINIO_BUCKET_ACCESS_LEN: usize = 20;
 pub const SECRET_MINIO_BUCKET_SECRET_LEN: usize = 35;
 
+pub const SECRET_CREATED_BY_LABEL: &str = "created-by";
+pub const SECRET_CREATED_BY_VALUE: &str = "miniok8sbuckets";
+
+pub const MINIO_BUCKET_FINALIZER: &str = "communiquons.org/minio-bucket-cleanup";
+
+/// The annotation of MinioBuckets that releases their finalizer without cleaning up Minio,
+/// when set to `true`
+pub const SKIP_CLEANUP_ANNOTATION: &str = "communiquons.org/skip-cleanup";
+
+/// The tag of Minio buckets that identifies the MinioBucket that manages them, as
+/// `namespace/name`
+pub const BUCKET_OWNER_TAG: &str = "communiquons.org/owner";
+
+/// The annotation of MinioBuckets that requests a rotation of their credentials, each time
+/// its value changes
+pub const ROTATE_CREDENTIALS_ANNOTATION: &str = "communiquons.org/rotate-credentials";
+
+/// The annotation of pod templates that is updated to restart the workloads that consume the
+/// secret of a bucket
+pub const RESTARTED_AT_ANNOTATION: &str = "communiquons.org/restartedAt";
+
+/// How long the previous credentials of a bucket remain valid after a rotation, unless the
+/// bucket overrides it
+pub const DEFAULT_ROTATION_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
 pub const MC_EXE: &str = "mc";
+
+/// How long the configuration of mc of a Minio instance is kept once it is not used anymore
+pub const MC_CONFIG_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// The region of Minio instances, used to sign requests and in the secrets of buckets
+pub const MINIO_REGION: &str = "us-east-1";
+
+/// The maximum duration of a request to a Minio instance, unless the instance overrides it
+pub const MINIO_DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The interval between two health checks of Minio instances
+pub const MINIO_INSTANCE_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The interval between two reconciliations of a bucket that did not change
+pub const MINIO_BUCKET_RESYNC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The delay before retrying a failed reconciliation. It is doubled after each
+/// consecutive failure, up to the maximum delay
+pub const RECONCILE_BACKOFF_MIN: Duration = Duration::from_secs(5);
+pub const RECONCILE_BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+
+/// The minimum delay before publishing again an identical event about an object
+pub const EVENT_REPEAT_INTERVAL: Duration = Duration::from_secs(60 * 60);
@@ -0,0 +1,287 @@
+//! # Controllers shared state
+//!
+//! State and error types shared by the controllers of the operator
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use k8s_openapi::NamespaceResourceScope;
+use kube::runtime::events::{Event, EventType, Recorder};
+use kube::runtime::reflector::ObjectRef;
+use kube::{Api, Client, Resource, ResourceExt};
+
+use crate::constants::{
+    EVENT_REPEAT_INTERVAL, OPERATOR_NAME, RECONCILE_BACKOFF_MAX, RECONCILE_BACKOFF_MIN,
+};
+use crate::minio::{McConfigs, MinioBackendKind};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReconcileError {
+    #[error("Minio instance {instance} is unreachable after {attempts} attempts!")]
+    InstanceUnreachable { instance: String, attempts: u32 },
+    #[error("Namespace {namespace} is not allowed to use Minio instance {instance}!")]
+    InstanceNotAllowed { instance: String, namespace: String },
+    #[error("Namespace selector of Minio instance {instance} is invalid: {error}")]
+    InvalidNamespaceSelector { instance: String, error: String },
+    #[error("Secret {secret} already exists and was not created by the operator!")]
+    SecretNotManaged { secret: String },
+    #[error("Bucket {bucket} is already managed by MinioBucket {owner}!")]
+    BucketNotOwned { bucket: String, owner: String },
+    #[error("Failed to restart the workloads that consume secret {secret}: {failures}")]
+    RestartFailed { secret: String, failures: String },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Extract the reconciliation error wrapped in an anyhow error, if any
+pub fn into_reconcile_error(e: anyhow::Error) -> ReconcileError {
+    match e.downcast::<ReconcileError>() {
+        Ok(e) => e,
+        Err(e) => ReconcileError::Other(e),
+    }
+}
+
+/// Context given to each reconciliation
+pub struct Context {
+    pub client: Client,
+    /// Used to publish Kubernetes events about reconciled objects
+    pub recorder: Recorder,
+    /// The namespaces watched by the operator. All namespaces are watched if empty
+    pub namespaces: Vec<String>,
+    /// The namespace where the operator is deployed
+    pub operator_namespace: String,
+    /// The way the operator communicates with Minio instances
+    pub backend: MinioBackendKind,
+    /// The number of consecutive failed reconciliations, per object
+    failures: Mutex<HashMap<String, u32>>,
+    /// The events recently published, used to avoid publishing the same event repeatedly
+    events: Mutex<RecentEvents>,
+    /// The configurations of mc, reused by the commands run against each Minio instance
+    pub mc_configs: McConfigs,
+}
+
+impl Context {
+    pub fn new(
+        client: Client,
+        namespaces: Vec<String>,
+        operator_namespace: String,
+        backend: MinioBackendKind,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            recorder: Recorder::new(client.clone(), OPERATOR_NAME.into()),
+            client,
+            namespaces,
+            operator_namespace,
+            backend,
+            failures: Default::default(),
+            events: Default::default(),
+            mc_configs: Default::default(),
+        })
+    }
+
+    /// Get the APIs to use to watch a kind of resources, one per watched namespace
+    pub fn watched_apis<K>(&self) -> Vec<Api<K>>
+    where
+        K: Resource<Scope = NamespaceResourceScope>,
+        K::DynamicType: Default,
+    {
+        if self.namespaces.is_empty() {
+            return vec![Api::all(self.client.clone())];
+        }
+
+        self.namespaces
+            .iter()
+            .map(|ns| Api::namespaced(self.client.clone(), ns))
+            .collect()
+    }
+
+    /// Record a failed reconciliation of an object, and get the delay to wait before the
+    /// next attempt
+    pub fn record_failure(&self, key: &str) -> Duration {
+        let mut failures = self.failures.lock().unwrap();
+        let count = failures.entry(key.to_string()).or_default();
+        *count = count.saturating_add(1);
+        backoff_delay(*count)
+    }
+
+    /// Forget the failed reconciliations and the recent events of an object, after a
+    /// successful reconciliation or once the object was deleted
+    pub fn forget(&self, key: &str) {
+        self.failures.lock().unwrap().remove(key);
+        self.events.lock().unwrap().forget(key);
+    }
+
+    /// Publish an event about an object, unless the same event was published recently
+    pub async fn publish_event<K: Resource<DynamicType = ()>>(
+        &self,
+        obj: &K,
+        type_: EventType,
+        reason: &str,
+        note: impl ToString,
+        action: &str,
+    ) {
+        let key = ObjectRef::from_obj(obj).to_string();
+        let note = note.to_string();
+        if !self
+            .events
+            .lock()
+            .unwrap()
+            .should_publish(&key, reason, &note, Instant::now())
+        {
+            log::debug!("Skip duplicate event {reason} for {key}");
+            return;
+        }
+
+        let event = Event {
+            type_,
+            reason: reason.to_string(),
+            note: Some(note),
+            action: action.to_string(),
+            secondary: None,
+        };
+        if let Err(e) = self.recorder.publish(&event, &obj.object_ref(&())).await {
+            log::error!("Failed to publish event {reason} for {key} : {e}");
+        }
+    }
+}
+
+/// The last note published for each reason of event, per object
+#[derive(Default)]
+struct RecentEvents(HashMap<String, HashMap<String, (String, Instant)>>);
+
+impl RecentEvents {
+    /// Check if an event must be published, and remember it if so. Identical events are
+    /// published again only once the repeat interval has elapsed
+    fn should_publish(&mut self, key: &str, reason: &str, note: &str, now: Instant) -> bool {
+        let events = self.0.entry(key.to_string()).or_default();
+        if let Some((last_note, last_time)) = events.get(reason) {
+            if last_note == note && now.duration_since(*last_time) < EVENT_REPEAT_INTERVAL {
+                return false;
+            }
+        }
+
+        events.insert(reason.to_string(), (note.to_string(), now));
+        true
+    }
+
+    /// Forget the events published about an object
+    fn forget(&mut self, key: &str) {
+        self.0.remove(key);
+    }
+}
+
+/// Get an API to manipulate the resources located in the namespace of an object
+pub fn namespaced_api<K, O>(client: &Client, obj: &O) -> Api<K>
+where
+    K: Resource<Scope = NamespaceResourceScope>,
+    K::DynamicType: Default,
+    O: ResourceExt,
+{
+    Api::namespaced(
+        client.clone(),
+        obj.namespace().as_deref().unwrap_or("default"),
+    )
+}
+
+/// Parse a comma separated list of namespaces. An empty list means all namespaces
+pub fn parse_namespaces(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|ns| ns.trim())
+        .filter(|ns| !ns.is_empty())
+        .map(|ns| ns.to_string())
+        .collect()
+}
+
+/// Get the delay to wait before retrying a reconciliation that failed a given number
+/// of consecutive times
+pub fn backoff_delay(failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    RECONCILE_BACKOFF_MIN
+        .saturating_mul(factor)
+        .min(RECONCILE_BACKOFF_MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use kube::runtime::reflector::ObjectRef;
+
+    use crate::constants::{EVENT_REPEAT_INTERVAL, RECONCILE_BACKOFF_MAX, RECONCILE_BACKOFF_MIN};
+    use crate::controller::{
+        backoff_delay, into_reconcile_error, parse_namespaces, RecentEvents, ReconcileError,
+    };
+    use crate::crd::MinioBucket;
+
+    #[test]
+    fn backoff_is_exponential() {
+        assert_eq!(backoff_delay(1), RECONCILE_BACKOFF_MIN);
+        assert_eq!(backoff_delay(2), RECONCILE_BACKOFF_MIN * 2);
+        assert_eq!(backoff_delay(3), RECONCILE_BACKOFF_MIN * 4);
+    }
+
+    #[test]
+    fn deleted_object_key() {
+        // Deleted objects are reported by the controllers with an erased reference, that must
+        // identify the same state as the reference of the object
+        let mut b = MinioBucket::new("bucket", Default::default());
+        b.metadata.namespace = Some("default".to_string());
+        let obj = ObjectRef::from_obj(&b);
+        assert_eq!(obj.clone().erase().to_string(), obj.to_string());
+    }
+
+    #[test]
+    fn parse_namespaces_list() {
+        assert!(parse_namespaces("").is_empty());
+        assert!(parse_namespaces(" , ").is_empty());
+        assert_eq!(parse_namespaces("default"), vec!["default"]);
+        assert_eq!(
+            parse_namespaces("team-a, team-b,"),
+            vec!["team-a", "team-b"]
+        );
+    }
+
+    #[test]
+    fn typed_error_is_extracted() {
+        let e: anyhow::Error = ReconcileError::InstanceUnreachable {
+            instance: "minio".to_string(),
+            attempts: 3,
+        }
+        .into();
+        assert!(matches!(
+            into_reconcile_error(e),
+            ReconcileError::InstanceUnreachable { attempts: 3, .. }
+        ));
+
+        assert!(matches!(
+            into_reconcile_error(anyhow::anyhow!("other")),
+            ReconcileError::Other(_)
+        ));
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff_delay(20), RECONCILE_BACKOFF_MAX);
+        assert_eq!(backoff_delay(u32::MAX), RECONCILE_BACKOFF_MAX);
+    }
+
+    #[test]
+    fn duplicate_events_are_skipped() {
+        let mut events = RecentEvents::default();
+        let now = Instant::now();
+        assert!(events.should_publish("bucket", "ReconcileFailed", "error", now));
+        assert!(!events.should_publish("bucket", "ReconcileFailed", "error", now));
+        assert!(events.should_publish("bucket", "ReconcileFailed", "other error", now));
+        assert!(events.should_publish("other", "ReconcileFailed", "other error", now));
+        assert!(events.should_publish(
+            "bucket",
+            "ReconcileFailed",
+            "other error",
+            now + EVENT_REPEAT_INTERVAL
+        ));
+
+        events.forget("bucket");
+        assert!(events.should_publish("bucket", "ReconcileFailed", "other error", now));
+    }
+}
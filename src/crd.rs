@@ -1,17 +1,220 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, LabelSelector, Time};
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::constants::{
+    DEFAULT_ROTATION_GRACE_PERIOD, MINIO_DEFAULT_REQUEST_TIMEOUT, SECRET_DEFAULT_ACCESS_KEY,
+    SECRET_DEFAULT_SECRET_KEY,
+};
+
 #[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
 #[kube(
     group = "communiquons.org",
     version = "v1",
     kind = "MinioInstance",
-    namespaced
+    namespaced,
+    status = "MinioInstanceStatus",
+    printcolumn = r#"{"name":"Endpoint", "type":"string", "jsonPath":".status.endpoint"}"#,
+    printcolumn = r#"{"name":"Reachable", "type":"string", "jsonPath":".status.conditions[?(@.type==\"Reachable\")].status"}"#,
+    printcolumn = r#"{"name":"Version", "type":"string", "jsonPath":".status.serverVersion"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
 )]
 pub struct MinioInstanceSpec {
-    pub endpoint: String,
+    /// The URL of the instance. Either this or `serviceRef` must be set
+    pub endpoint: Option<String>,
+    /// The Kubernetes Service exposing the instance, used instead of a raw URL
+    #[serde(rename = "serviceRef")]
+    pub service_ref: Option<ServiceRef>,
     pub credentials: String,
+    /// The keys of the access key and of the secret key in the credentials secret
+    #[serde(default, rename = "credentialsKeys")]
+    pub credentials_keys: CredentialsKeys,
+    #[serde(default, rename = "readinessProbe")]
+    pub readiness_probe: ReadinessProbe,
+    /// The maximum duration of a request to the instance, in seconds. Requests that take
+    /// longer are aborted
+    #[serde(rename = "requestTimeoutSecs")]
+    pub request_timeout_secs: Option<u64>,
+    /// How the operator connects to the instance when it is served over TLS
+    #[serde(default)]
+    pub tls: InstanceTls,
+}
+
+impl MinioInstanceSpec {
+    /// Get the maximum duration of a request to the instance
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(MINIO_DEFAULT_REQUEST_TIMEOUT)
+    }
+}
+
+/// A Minio instance that can be shared by buckets of several namespaces. Its credentials
+/// secret is located in the namespace of the operator
+#[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[kube(
+    group = "communiquons.org",
+    version = "v1",
+    kind = "ClusterMinioInstance",
+    status = "MinioInstanceStatus",
+    printcolumn = r#"{"name":"Endpoint", "type":"string", "jsonPath":".status.endpoint"}"#,
+    printcolumn = r#"{"name":"Reachable", "type":"string", "jsonPath":".status.conditions[?(@.type==\"Reachable\")].status"}"#,
+    printcolumn = r#"{"name":"Version", "type":"string", "jsonPath":".status.serverVersion"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
+)]
+pub struct ClusterMinioInstanceSpec {
+    #[serde(flatten)]
+    pub instance: MinioInstanceSpec,
+    /// The namespaces allowed to create buckets on this instance
+    #[serde(default, rename = "allowedNamespaces")]
+    pub allowed_namespaces: Vec<String>,
+    /// Namespaces whose labels match this selector are also allowed to create buckets on
+    /// this instance
+    #[serde(rename = "namespaceSelector")]
+    pub namespace_selector: Option<LabelSelector>,
+}
+
+/// A Kubernetes Service exposing a Minio instance
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema, PartialEq, Eq)]
+pub struct ServiceRef {
+    pub name: String,
+    /// The namespace of the Service. Defaults to the namespace of the MinioInstance, or to the
+    /// namespace of the operator for a ClusterMinioInstance
+    pub namespace: Option<String>,
+    /// The name of the port of the Service. Defaults to its first port
+    pub port: Option<String>,
+    #[serde(default)]
+    pub scheme: ServiceScheme,
+}
+
+/// The protocol used to reach a Service
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceScheme {
+    #[default]
+    Http,
+    Https,
+}
+
+impl ServiceScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceScheme::Http => "http",
+            ServiceScheme::Https => "https",
+        }
+    }
+}
+
+/// How the operator connects to a Minio instance served over TLS
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceTls {
+    /// A secret containing certificate authorities, trusted in addition to the system ones
+    pub ca_secret: Option<KeySelector>,
+    /// A config map containing certificate authorities, trusted in addition to the system ones
+    pub ca_config_map: Option<KeySelector>,
+    /// Do not verify the certificate of the instance. This is insecure
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    /// A secret of type `kubernetes.io/tls` containing the certificate presented to the
+    /// instance
+    pub client_cert_secret: Option<String>,
+}
+
+/// A key of a secret or of a config map
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+pub struct KeySelector {
+    pub name: String,
+    #[serde(default = "default_ca_key")]
+    pub key: String,
+}
+
+fn default_ca_key() -> String {
+    "ca.crt".to_string()
+}
+
+/// The keys of a secret that contain an access key and a secret key
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsKeys {
+    #[serde(default = "default_access_key")]
+    pub access_key: String,
+    #[serde(default = "default_secret_key")]
+    pub secret_key: String,
+}
+
+fn default_access_key() -> String {
+    SECRET_DEFAULT_ACCESS_KEY.to_string()
+}
+
+fn default_secret_key() -> String {
+    SECRET_DEFAULT_SECRET_KEY.to_string()
+}
+
+impl Default for CredentialsKeys {
+    fn default() -> Self {
+        Self {
+            access_key: default_access_key(),
+            secret_key: default_secret_key(),
+        }
+    }
+}
+
+/// How the operator checks that a Minio instance is responding before using it
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessProbe {
+    /// The number of health checks performed before the instance is considered unreachable
+    #[serde(default = "default_probe_attempts")]
+    pub attempts: u32,
+    /// The delay between two health checks, in milliseconds
+    #[serde(default = "default_probe_interval_ms")]
+    pub interval_ms: u64,
+}
+
+fn default_probe_attempts() -> u32 {
+    10
+}
+
+fn default_probe_interval_ms() -> u64 {
+    500
+}
+
+impl Default for ReadinessProbe {
+    fn default() -> Self {
+        Self {
+            attempts: default_probe_attempts(),
+            interval_ms: default_probe_interval_ms(),
+        }
+    }
+}
+
+/// Observed state of a MinioInstance
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MinioInstanceStatus {
+    /// The generation of the MinioInstance that was last probed
+    pub observed_generation: Option<i64>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// The last time the instance was probed
+    pub last_probe_time: Option<Time>,
+    /// The URL the instance was last probed at
+    pub endpoint: Option<String>,
+    /// The version of the Minio server
+    pub server_version: Option<String>,
+    /// The mode of the Minio server (online, initializing...)
+    pub mode: Option<String>,
+    pub drives_online: Option<usize>,
+    pub drives_offline: Option<usize>,
+    /// Total capacity of the drives, in bytes
+    pub total_capacity: Option<u64>,
+    /// Used capacity of the drives, in bytes
+    pub used_capacity: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, JsonSchema, PartialEq, Eq)]
@@ -29,17 +232,107 @@ pub struct BucketRetention {
     pub r#type: RetentionType,
 }
 
+/// What should happen to Minio resources once a MinioBucket is deleted
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, JsonSchema, PartialEq, Eq)]
+pub enum DeletionPolicy {
+    /// Keep the bucket, its user, its policy and its secret
+    #[default]
+    Retain,
+    /// Remove the user, its policy and its secret, but keep the bucket
+    DeleteUserOnly,
+    /// Same as `DeleteUserOnly`, and remove the bucket if it does not contain any object
+    DeleteIfEmpty,
+    /// Remove everything, including the bucket and all its content
+    Delete,
+}
+
+/// What should happen when the settings of a bucket differ from its desired configuration
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, JsonSchema, PartialEq, Eq)]
+pub enum DriftPolicy {
+    /// Restore the desired configuration, and report the change
+    #[default]
+    Correct,
+    /// Only report the difference
+    Report,
+}
+
+/// Connection information that can be added to the secret of a bucket
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, JsonSchema, PartialEq, Eq)]
+pub enum SecretFormat {
+    /// The URL of the instance, in the `endpoint` key
+    Endpoint,
+    /// The name of the bucket, in the `bucket` key
+    Bucket,
+    /// The region of the instance, in the `region` key
+    Region,
+    /// The `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_ENDPOINT_URL` and `AWS_REGION`
+    /// keys, that can be loaded as environment variables
+    AwsEnv,
+    /// An rclone configuration file, in the `rclone.conf` key
+    Rclone,
+    /// An s3cmd configuration file, in the `.s3cfg` key
+    S3cmd,
+    /// An mc configuration file, in the `config.json` key
+    Mc,
+}
+
+/// The kind of resource that describes the Minio instance of a bucket
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, JsonSchema, PartialEq, Eq)]
+pub enum InstanceKind {
+    /// A MinioInstance located in the namespace of the bucket
+    #[default]
+    MinioInstance,
+    /// A ClusterMinioInstance which allows the namespace of the bucket
+    ClusterMinioInstance,
+}
+
 #[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
 #[kube(
     group = "communiquons.org",
     version = "v1",
     kind = "MinioBucket",
-    namespaced
+    namespaced,
+    status = "MinioBucketStatus",
+    printcolumn = r#"{"name":"Bucket", "type":"string", "jsonPath":".status.bucketName"}"#,
+    printcolumn = r#"{"name":"Secret", "type":"string", "jsonPath":".status.secretName"}"#,
+    printcolumn = r#"{"name":"Ready", "type":"string", "jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
 )]
 pub struct MinioBucketSpec {
     pub instance: String,
+    #[serde(default, rename = "instanceKind")]
+    pub instance_kind: InstanceKind,
     pub name: String,
     pub secret: String,
+    /// The keys of the access key and of the secret key in the secret of the bucket
+    #[serde(default, rename = "secretKeys")]
+    pub secret_keys: CredentialsKeys,
+    /// Additional connection information written to the secret of the bucket
+    #[serde(default, rename = "secretFormat")]
+    pub secret_format: Vec<SecretFormat>,
+    /// Additional keys of the secret of the bucket, whose values are rendered from templates.
+    /// The templates can reference `{{accessKey}}`, `{{secretKey}}`, `{{endpoint}}`,
+    /// `{{bucket}}` and `{{region}}`, and can not override the credentials nor the keys of
+    /// `secretFormat`
+    #[serde(default, rename = "secretTemplate")]
+    pub secret_template: BTreeMap<String, String>,
+    /// Labels added to the secret of the bucket
+    #[serde(default, rename = "secretLabels")]
+    pub secret_labels: BTreeMap<String, String>,
+    /// Annotations added to the secret of the bucket
+    #[serde(default, rename = "secretAnnotations")]
+    pub secret_annotations: BTreeMap<String, String>,
+    /// The type of the secret of the bucket, `Opaque` by default
+    #[serde(rename = "secretType")]
+    pub secret_type: Option<String>,
+    /// Mark the secret of the bucket as immutable. It is then created again when its content
+    /// changes
+    #[serde(default, rename = "secretImmutable")]
+    pub secret_immutable: bool,
+    /// Make the MinioBucket the owner of its secret, so that the secret is deleted along with
+    /// it. Defaults to true, unless the deletion policy is `Retain`
+    #[serde(rename = "secretOwnerReference")]
+    pub secret_owner_reference: Option<bool>,
     #[serde(default)]
     pub anonymous_read_access: bool,
     #[serde(default)]
@@ -48,4 +341,117 @@ pub struct MinioBucketSpec {
     #[serde(default)]
     pub lock: bool,
     pub retention: Option<BucketRetention>,
+    #[serde(default, rename = "deletionPolicy")]
+    pub deletion_policy: DeletionPolicy,
+    #[serde(default, rename = "driftPolicy")]
+    pub drift_policy: DriftPolicy,
+    /// How the credentials of the user of the bucket are rotated
+    pub rotation: Option<CredentialsRotation>,
+    /// The workloads restarted when the operator rewrites the secret of the bucket
+    #[serde(default, rename = "restartOnSecretChange")]
+    pub restart_on_secret_change: RestartWorkloads,
+}
+
+/// Workloads of the namespace of a bucket that consume its secret
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema, PartialEq)]
+pub struct RestartWorkloads {
+    #[serde(default)]
+    pub workloads: Vec<WorkloadRef>,
+    /// Deployments, StatefulSets and DaemonSets whose labels match this selector are also
+    /// restarted
+    pub selector: Option<LabelSelector>,
+}
+
+impl RestartWorkloads {
+    pub fn is_empty(&self) -> bool {
+        self.workloads.is_empty() && self.selector.is_none()
+    }
+}
+
+/// A workload of the namespace of a bucket
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+pub struct WorkloadRef {
+    pub kind: WorkloadKind,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, JsonSchema, PartialEq, Eq)]
+pub enum WorkloadKind {
+    Deployment,
+    StatefulSet,
+    DaemonSet,
+}
+
+/// How the credentials of the user of a bucket are rotated. Credentials are also rotated each
+/// time the `communiquons.org/rotate-credentials` annotation of the bucket changes
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsRotation {
+    /// The interval between two rotations, in seconds. If not set, credentials are only
+    /// rotated on demand
+    pub interval_secs: Option<u64>,
+    /// How long the previous credentials remain valid after a rotation, in seconds
+    pub grace_period_secs: Option<u64>,
+}
+
+impl CredentialsRotation {
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval_secs.map(Duration::from_secs)
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_ROTATION_GRACE_PERIOD)
+    }
+}
+
+impl MinioBucketSpec {
+    /// Check if the MinioBucket must be the owner of its secret
+    pub fn secret_owned(&self) -> bool {
+        self.secret_owner_reference
+            .unwrap_or(self.deletion_policy != DeletionPolicy::Retain)
+    }
+}
+
+/// Observed state of a MinioBucket
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MinioBucketStatus {
+    /// The generation of the MinioBucket that was last reconciled
+    pub observed_generation: Option<i64>,
+    /// The generation of the MinioBucket whose settings were last applied to the bucket
+    pub applied_generation: Option<i64>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// The error that occurred during the last reconciliation, if any
+    pub last_error: Option<String>,
+    /// The name of the bucket on the Minio instance
+    pub bucket_name: Option<String>,
+    /// The name of the secret that contains the credentials of the bucket
+    pub secret_name: Option<String>,
+    /// The keys of the secret where the credentials of the bucket were last written
+    pub secret_keys: Option<CredentialsKeys>,
+    /// The name of the Minio user whose credentials are stored in the secret
+    pub username: Option<String>,
+    /// The last time the credentials of the bucket were rotated
+    pub last_rotation_time: Option<Time>,
+    /// The value of the rotation annotation of the bucket that was last handled
+    pub rotation_request: Option<String>,
+    /// The secret of the bucket was rewritten, and the workloads that consume it must still be
+    /// restarted
+    #[serde(default)]
+    pub restart_pending: bool,
+    /// The previous users of the bucket, removed once their grace period is over
+    #[serde(default)]
+    pub retired_users: Vec<RetiredUser>,
+}
+
+/// A previous user of a bucket, whose credentials remain valid for a while after a rotation
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RetiredUser {
+    pub username: String,
+    /// When the user is removed
+    pub expiration_time: Time,
 }
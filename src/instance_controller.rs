@@ -0,0 +1,500 @@
+//! # Minio instances controller
+//!
+//! Periodically checks the health of Minio instances (MinioInstance and ClusterMinioInstance
+//! resources), and reports it in their status
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{FutureExt, StreamExt};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret, Service};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::jiff::Timestamp;
+use kube::api::{Patch, PatchParams};
+use kube::runtime::controller::{self, Action};
+use kube::runtime::events::EventType;
+use kube::runtime::reflector::ObjectRef;
+use kube::runtime::{watcher, Controller};
+use kube::{Api, Client, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+
+use crate::conditions::{is_condition_true, set_condition, CONDITION_REACHABLE};
+use crate::constants::{MINIO_INSTANCE_PROBE_INTERVAL, SECRET_TLS_CERT, SECRET_TLS_KEY};
+use crate::controller::{namespaced_api, Context, ReconcileError};
+use crate::crd::{
+    ClusterMinioInstance, InstanceTls, MinioInstance, MinioInstanceSpec, MinioInstanceStatus,
+    ReadinessProbe, ServiceRef,
+};
+use crate::minio::{
+    ClientCertificate, McConfig, MinioBackend, MinioServerInfo, MinioService, MinioTls,
+};
+use crate::secrets::{read_config_map_str, read_secret_str};
+
+#[derive(thiserror::Error, Debug)]
+enum InstanceError {
+    #[error("Minio instance must define either an endpoint or a serviceRef!")]
+    InvalidEndpoint,
+    #[error("Service {0} does not have any port!")]
+    NoServicePort(String),
+    #[error("Service {0} does not have a port named {1}!")]
+    NoSuchServicePort(String, String),
+}
+
+/// A resource that describes how to reach a Minio instance
+pub trait InstanceResource:
+    Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + Sync + 'static
+{
+    fn instance_spec(&self) -> &MinioInstanceSpec;
+
+    fn instance_status(&self) -> Option<&MinioInstanceStatus>;
+
+    /// Get the API to use to manipulate this resource
+    fn api(&self, client: &Client) -> Api<Self>;
+
+    /// Get the namespace where the credentials secret of the instance is located
+    fn credentials_namespace(&self, ctx: &Context) -> String;
+}
+
+impl InstanceResource for MinioInstance {
+    fn instance_spec(&self) -> &MinioInstanceSpec {
+        &self.spec
+    }
+
+    fn instance_status(&self) -> Option<&MinioInstanceStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, client: &Client) -> Api<Self> {
+        namespaced_api(client, self)
+    }
+
+    fn credentials_namespace(&self, _ctx: &Context) -> String {
+        self.namespace().unwrap_or_default()
+    }
+}
+
+impl InstanceResource for ClusterMinioInstance {
+    fn instance_spec(&self) -> &MinioInstanceSpec {
+        &self.spec.instance
+    }
+
+    fn instance_status(&self) -> Option<&MinioInstanceStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, client: &Client) -> Api<Self> {
+        Api::all(client.clone())
+    }
+
+    fn credentials_namespace(&self, ctx: &Context) -> String {
+        ctx.operator_namespace.clone()
+    }
+}
+
+/// Run the controllers of Minio instances, until the operator is asked to stop
+pub async fn run(ctx: Arc<Context>) {
+    let instances = ctx
+        .watched_apis::<MinioInstance>()
+        .into_iter()
+        .map(|api| run_controller(api, ctx.clone()).boxed());
+    let cluster_instances =
+        run_controller::<ClusterMinioInstance>(Api::all(ctx.client.clone()), ctx.clone()).boxed();
+    futures::future::join_all(instances.chain([cluster_instances])).await;
+}
+
+async fn run_controller<K: InstanceResource>(api: Api<K>, ctx: Arc<Context>) {
+    // Instances are probed again when the Service they reference changes
+    let controller = Controller::new(api, watcher::Config::default());
+    let store = controller.store();
+    service_apis(&ctx)
+        .into_iter()
+        .fold(controller, |controller, services| {
+            let store = store.clone();
+            let ctx = ctx.clone();
+            controller.watches(services, watcher::Config::default(), move |service| {
+                store
+                    .state()
+                    .into_iter()
+                    .filter(|i| references_service(i.as_ref(), &service, &ctx))
+                    .map(|i| ObjectRef::from_obj(i.as_ref()))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .shutdown_on_signal()
+        .run(reconcile, error_policy, ctx.clone())
+        .for_each(|res| {
+            match res {
+                // The instance was deleted before a scheduled reconciliation
+                Err(controller::Error::ObjectNotFound(obj)) => ctx.forget(&obj.to_string()),
+                Err(e) => log::warn!("Minio instance reconciliation error: {e}"),
+                Ok(_) => {}
+            }
+            futures::future::ready(())
+        })
+        .await
+}
+
+/// Get the APIs to use to watch the Services referenced by instances. The Services of
+/// ClusterMinioInstances are located by default in the namespace of the operator, that is
+/// watched even if it is not among the watched namespaces
+fn service_apis(ctx: &Context) -> Vec<Api<Service>> {
+    let mut apis = ctx.watched_apis::<Service>();
+    if !ctx.namespaces.is_empty() && !ctx.namespaces.contains(&ctx.operator_namespace) {
+        apis.push(Api::namespaced(ctx.client.clone(), &ctx.operator_namespace));
+    }
+    apis
+}
+
+/// Check if an instance references a Service
+fn references_service<K: InstanceResource>(instance: &K, service: &Service, ctx: &Context) -> bool {
+    match &instance.instance_spec().service_ref {
+        Some(sref) => {
+            sref.name == service.name_any()
+                && service.namespace() == Some(service_namespace(instance, sref, ctx))
+        }
+        None => false,
+    }
+}
+
+/// Get the namespace of the Service referenced by an instance
+fn service_namespace<K: InstanceResource>(
+    instance: &K,
+    sref: &ServiceRef,
+    ctx: &Context,
+) -> String {
+    sref.namespace
+        .clone()
+        .unwrap_or_else(|| instance.credentials_namespace(ctx))
+}
+
+/// Get the URL of a Minio instance, resolving the Service it references if required
+pub async fn instance_endpoint<K: InstanceResource>(
+    instance: &K,
+    ctx: &Context,
+) -> anyhow::Result<String> {
+    let spec = instance.instance_spec();
+    match (&spec.endpoint, &spec.service_ref) {
+        (Some(endpoint), None) => Ok(endpoint.clone()),
+        (None, Some(sref)) => {
+            let services: Api<Service> =
+                Api::namespaced(ctx.client.clone(), &service_namespace(instance, sref, ctx));
+            service_endpoint(&services.get(&sref.name).await?, sref)
+        }
+        _ => Err(InstanceError::InvalidEndpoint.into()),
+    }
+}
+
+/// Get the cluster DNS URL of a Service
+fn service_endpoint(service: &Service, sref: &ServiceRef) -> anyhow::Result<String> {
+    let name = service.name_any();
+    let ports = service
+        .spec
+        .as_ref()
+        .and_then(|s| s.ports.as_ref())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let port = match &sref.port {
+        None => ports
+            .first()
+            .ok_or_else(|| InstanceError::NoServicePort(name.clone()))?,
+        Some(port_name) => ports
+            .iter()
+            .find(|p| p.name.as_ref() == Some(port_name))
+            .ok_or_else(|| InstanceError::NoSuchServicePort(name.clone(), port_name.clone()))?,
+    };
+
+    Ok(format!(
+        "{}://{name}.{}.svc:{}",
+        sref.scheme.as_str(),
+        service.namespace().unwrap_or_default(),
+        port.port
+    ))
+}
+
+/// Get the delay before the next probe of an instance, if it has been probed recently at the
+/// same endpoint and has not changed since then
+fn next_probe_delay<K: InstanceResource>(instance: &K, endpoint: Option<&str>) -> Option<Duration> {
+    let status = instance.instance_status()?;
+    if status.observed_generation != instance.meta().generation
+        || status.endpoint.as_deref() != endpoint
+    {
+        return None;
+    }
+
+    let last_probe = status.last_probe_time.as_ref()?;
+    let elapsed = Duration::try_from(Timestamp::now().duration_since(last_probe.0)).ok()?;
+    MINIO_INSTANCE_PROBE_INTERVAL
+        .checked_sub(elapsed)
+        .filter(|d| !d.is_zero())
+}
+
+async fn reconcile<K: InstanceResource>(
+    instance: Arc<K>,
+    ctx: Arc<Context>,
+) -> Result<Action, ReconcileError> {
+    // Updating the status of an instance triggers a new reconciliation, which must not
+    // probe the instance again, unless its endpoint changed with the Service it references
+    let endpoint = instance_endpoint(instance.as_ref(), &ctx).await.ok();
+    if let Some(delay) = next_probe_delay(instance.as_ref(), endpoint.as_deref()) {
+        return Ok(Action::requeue(delay));
+    }
+
+    probe_instance(instance.as_ref(), &ctx, endpoint).await?;
+    ctx.forget(&ObjectRef::from_obj(instance.as_ref()).to_string());
+    Ok(Action::requeue(MINIO_INSTANCE_PROBE_INTERVAL))
+}
+
+fn error_policy<K: InstanceResource>(
+    instance: Arc<K>,
+    e: &ReconcileError,
+    ctx: Arc<Context>,
+) -> Action {
+    let delay = ctx.record_failure(&ObjectRef::from_obj(instance.as_ref()).to_string());
+    log::error!(
+        "Failed to probe Minio instance {}, will retry in {delay:?} : {e}",
+        instance.name_any()
+    );
+    Action::requeue(delay)
+}
+
+/// Get a connection to a Minio instance, using the credentials of the instance
+pub async fn instance_service<K: InstanceResource>(
+    instance: &K,
+    ctx: &Context,
+) -> anyhow::Result<MinioService> {
+    let spec = instance.instance_spec();
+    let secrets: Api<Secret> =
+        Api::namespaced(ctx.client.clone(), &instance.credentials_namespace(ctx));
+    let instance_secret = secrets.get(&spec.credentials).await?;
+    let endpoint = instance_endpoint(instance, ctx).await?;
+    let access_key = read_secret_str(&instance_secret, &spec.credentials_keys.access_key)?;
+    let secret_key = read_secret_str(&instance_secret, &spec.credentials_keys.secret_key)?;
+    let tls = instance_tls(&spec.tls, &instance.credentials_namespace(ctx), ctx).await?;
+    let mc_config = ctx.mc_configs.get(
+        &ObjectRef::from_obj(instance).to_string(),
+        &McConfig::fingerprint(&endpoint, &access_key, &secret_key, &tls),
+        &tls,
+    )?;
+    Ok(MinioService {
+        hostname: endpoint,
+        access_key,
+        secret_key,
+        backend: ctx.backend,
+        timeout: spec.request_timeout(),
+        tls,
+        mc_config,
+    })
+}
+
+/// Get the TLS settings of a Minio instance, reading the certificates it references in a
+/// namespace
+async fn instance_tls(
+    spec: &InstanceTls,
+    namespace: &str,
+    ctx: &Context,
+) -> anyhow::Result<MinioTls> {
+    let secrets: Api<Secret> = Api::namespaced(ctx.client.clone(), namespace);
+    let config_maps: Api<ConfigMap> = Api::namespaced(ctx.client.clone(), namespace);
+
+    let mut cas = vec![];
+    if let Some(sel) = &spec.ca_secret {
+        cas.push(read_secret_str(&secrets.get(&sel.name).await?, &sel.key)?);
+    }
+    if let Some(sel) = &spec.ca_config_map {
+        cas.push(read_config_map_str(
+            &config_maps.get(&sel.name).await?,
+            &sel.key,
+        )?);
+    }
+
+    let client_cert = match &spec.client_cert_secret {
+        None => None,
+        Some(name) => {
+            let secret = secrets.get(name).await?;
+            Some(ClientCertificate {
+                cert_pem: read_secret_str(&secret, SECRET_TLS_CERT)?,
+                key_pem: read_secret_str(&secret, SECRET_TLS_KEY)?,
+            })
+        }
+    };
+
+    Ok(MinioTls {
+        ca_pem: (!cas.is_empty()).then(|| cas.join("\n")),
+        insecure_skip_verify: spec.insecure_skip_verify,
+        client_cert,
+    })
+}
+
+/// Wait for a Minio instance to respond, using the probe settings of the instance. Returns
+/// false if the instance is still not responding after the last attempt
+pub async fn wait_instance_ready(minio: &dyn MinioBackend, probe: &ReadinessProbe) -> bool {
+    for attempt in 1..=probe.attempts.max(1) {
+        if minio.is_ready().await {
+            return true;
+        }
+
+        if attempt < probe.attempts {
+            log::warn!("Minio is not responding yet, will try again to connect soon...");
+            tokio::time::sleep(Duration::from_millis(probe.interval_ms)).await;
+        }
+    }
+
+    false
+}
+
+/// Get information about a Minio instance, once it responds
+async fn instance_info<K: InstanceResource>(
+    instance: &K,
+    ctx: &Context,
+    probe: &ReadinessProbe,
+) -> anyhow::Result<MinioServerInfo> {
+    let service = instance_service(instance, ctx).await?;
+    let minio = service.backend()?;
+    if !wait_instance_ready(minio.as_ref(), probe).await {
+        anyhow::bail!("Minio instance at {} is not responding", service.hostname);
+    }
+    minio.server_info().await
+}
+
+/// Check the health of a Minio instance, and save it in its status
+async fn probe_instance<K: InstanceResource>(
+    instance: &K,
+    ctx: &Context,
+    endpoint: Option<String>,
+) -> anyhow::Result<()> {
+    log::debug!("Probe Minio instance {}", instance.name_any());
+    let generation = instance.meta().generation;
+    let mut status = instance.instance_status().cloned().unwrap_or_default();
+    let previous = status
+        .conditions
+        .iter()
+        .find(|c| c.type_ == CONDITION_REACHABLE)
+        .map(|c| c.status == "True");
+    status.observed_generation = generation;
+    status.last_probe_time = Some(Time(Timestamp::now()));
+    status.endpoint = endpoint;
+
+    let probe = &instance.instance_spec().readiness_probe;
+    let info = instance_info(instance, ctx, probe).await;
+
+    match &info {
+        Ok(info) => {
+            set_condition(
+                &mut status.conditions,
+                CONDITION_REACHABLE,
+                true,
+                "Reachable",
+                "Minio instance is responding",
+                generation,
+            );
+            status.server_version = info.version.clone();
+            status.mode = info.mode.clone();
+            status.drives_online = Some(info.drives_online);
+            status.drives_offline = Some(info.drives_offline);
+            status.total_capacity = Some(info.total_capacity);
+            status.used_capacity = Some(info.used_capacity);
+        }
+        Err(e) => {
+            log::warn!("Minio instance {} is unhealthy : {e}", instance.name_any());
+            set_condition(
+                &mut status.conditions,
+                CONDITION_REACHABLE,
+                false,
+                "Unreachable",
+                e,
+                generation,
+            );
+        }
+    }
+
+    // Events are only published when the instance becomes reachable or unreachable
+    let reachable = is_condition_true(&status.conditions, CONDITION_REACHABLE);
+    if previous != Some(reachable) {
+        match &info {
+            Ok(_) => {
+                ctx.publish_event(
+                    instance,
+                    EventType::Normal,
+                    "Reachable",
+                    "Minio instance is responding",
+                    "Probe",
+                )
+                .await
+            }
+            Err(e) => {
+                ctx.publish_event(instance, EventType::Warning, "Unreachable", e, "Probe")
+                    .await
+            }
+        }
+    }
+
+    let patch = serde_json::json!({ "status": status });
+    instance
+        .api(&ctx.client)
+        .patch_status(
+            &instance.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use k8s_openapi::api::core::v1::{Service, ServicePort, ServiceSpec};
+    use kube::api::ObjectMeta;
+
+    use crate::crd::{ServiceRef, ServiceScheme};
+    use crate::instance_controller::service_endpoint;
+
+    fn test_service(ports: &[(&str, i32)]) -> Service {
+        Service {
+            metadata: ObjectMeta {
+                name: Some("minio".to_string()),
+                namespace: Some("storage".to_string()),
+                ..Default::default()
+            },
+            spec: Some(ServiceSpec {
+                ports: Some(
+                    ports
+                        .iter()
+                        .map(|(name, port)| ServicePort {
+                            name: Some(name.to_string()),
+                            port: *port,
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resolve_service_endpoint() {
+        let service = test_service(&[("api", 9000), ("console", 9001)]);
+        let mut sref = ServiceRef {
+            name: "minio".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            service_endpoint(&service, &sref).unwrap(),
+            "http://minio.storage.svc:9000"
+        );
+
+        sref.port = Some("console".to_string());
+        sref.scheme = ServiceScheme::Https;
+        assert_eq!(
+            service_endpoint(&service, &sref).unwrap(),
+            "https://minio.storage.svc:9001"
+        );
+
+        sref.port = Some("other".to_string());
+        assert!(service_endpoint(&service, &sref).is_err());
+        assert!(service_endpoint(&test_service(&[]), &ServiceRef::default()).is_err());
+    }
+}
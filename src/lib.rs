@@ -1,8 +1,23 @@
+pub mod bucket_controller;
+pub mod bucket_secret;
+pub mod conditions;
 pub mod constants;
+pub mod controller;
 pub mod crd;
+pub mod instance_controller;
+pub mod madmin;
+pub mod metrics;
 pub mod minio;
+pub mod minio_api;
+pub mod minio_error;
+#[cfg(test)]
+pub mod minio_fake;
 #[cfg(test)]
 pub mod minio_test_server;
+pub mod rotation;
 pub mod secrets;
+pub mod selectors;
+pub mod sigv4;
 pub mod temp;
 pub mod utils;
+pub mod workloads;
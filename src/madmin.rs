@@ -0,0 +1,196 @@
+//! # Minio admin payloads
+//!
+//! Some requests and responses of the Minio admin API are encrypted with the secret key of the
+//! caller. The payloads use the format of the `madmin` Go package: a 32 bytes salt, the id of
+//! the algorithm, an 8 bytes nonce, and the data encrypted as a `sio` stream
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use argon2::{Argon2, Params};
+use chacha20poly1305::ChaCha20Poly1305;
+use rand::RngExt;
+
+const SALT_LEN: usize = 32;
+const NONCE_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+/// The size of the fragments of the stream
+const FRAGMENT_LEN: usize = 1 << 14;
+
+const ARGON2ID_AES_GCM: u8 = 0x00;
+const ARGON2ID_CHACHA20_POLY1305: u8 = 0x01;
+const PBKDF2_AES_GCM: u8 = 0x02;
+
+const PBKDF2_ROUNDS: u32 = 8192;
+
+#[derive(thiserror::Error, Debug)]
+enum MadminError {
+    #[error("Encrypted admin payload is too short!")]
+    TooShort,
+    #[error("Unknown admin payload encryption algorithm {0}!")]
+    UnknownAlgorithm(u8),
+    #[error("Failed to derive admin payload encryption key!")]
+    KeyDerivation,
+    #[error("Admin payload is not authentic!")]
+    NotAuthentic,
+}
+
+enum Cipher {
+    AesGcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(Box<ChaCha20Poly1305>),
+}
+
+impl Cipher {
+    /// Derive the key of the cipher from a password, according to the id of the algorithm
+    fn new(id: u8, password: &str, salt: &[u8]) -> anyhow::Result<Self> {
+        let mut key = [0u8; 32];
+        match id {
+            ARGON2ID_AES_GCM | ARGON2ID_CHACHA20_POLY1305 => {
+                let params = Params::new(64 * 1024, 1, 4, Some(32))
+                    .map_err(|_| MadminError::KeyDerivation)?;
+                Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(password.as_bytes(), salt, &mut key)
+                    .map_err(|_| MadminError::KeyDerivation)?;
+            }
+            PBKDF2_AES_GCM => {
+                pbkdf2::pbkdf2_hmac::<sha2::Sha256>(
+                    password.as_bytes(),
+                    salt,
+                    PBKDF2_ROUNDS,
+                    &mut key,
+                );
+            }
+            id => return Err(MadminError::UnknownAlgorithm(id).into()),
+        }
+
+        Ok(match id {
+            ARGON2ID_CHACHA20_POLY1305 => Self::ChaCha20Poly1305(Box::new(
+                ChaCha20Poly1305::new_from_slice(&key).map_err(|_| MadminError::KeyDerivation)?,
+            )),
+            _ => Self::AesGcm(Box::new(
+                Aes256Gcm::new_from_slice(&key).map_err(|_| MadminError::KeyDerivation)?,
+            )),
+        })
+    }
+
+    fn seal(&self, nonce: &[u8; 12], aad: &[u8], msg: &[u8]) -> Vec<u8> {
+        let payload = Payload { msg, aad };
+        match self {
+            Self::AesGcm(c) => c.encrypt(nonce.into(), payload),
+            Self::ChaCha20Poly1305(c) => c.encrypt(nonce.into(), payload),
+        }
+        .expect("Encryption of admin payloads cannot fail")
+    }
+
+    fn open(&self, nonce: &[u8; 12], aad: &[u8], msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let payload = Payload { msg, aad };
+        Ok(match self {
+            Self::AesGcm(c) => c.decrypt(nonce.into(), payload),
+            Self::ChaCha20Poly1305(c) => c.decrypt(nonce.into(), payload),
+        }
+        .map_err(|_| MadminError::NotAuthentic)?)
+    }
+}
+
+/// Get the nonce of a fragment of the stream
+fn fragment_nonce(nonce: &[u8], seq_num: u32) -> [u8; 12] {
+    let mut out = [0u8; 12];
+    out[..NONCE_LEN].copy_from_slice(nonce);
+    out[NONCE_LEN..].copy_from_slice(&seq_num.to_le_bytes());
+    out
+}
+
+/// Get the associated data of the fragments of the stream. Its first byte must be changed
+/// for the last fragment
+fn stream_aad(cipher: &Cipher, nonce: &[u8]) -> Vec<u8> {
+    let mut aad = vec![0u8];
+    aad.extend(cipher.seal(&fragment_nonce(nonce, 0), &[], &[]));
+    aad
+}
+
+/// Encrypt an admin request payload with a password
+pub fn encrypt_data(password: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill(&mut salt);
+    rand::rng().fill(&mut nonce);
+    encrypt_with(password, data, &salt, &nonce)
+}
+
+fn encrypt_with(
+    password: &str,
+    data: &[u8],
+    salt: &[u8; SALT_LEN],
+    nonce: &[u8; NONCE_LEN],
+) -> anyhow::Result<Vec<u8>> {
+    let cipher = Cipher::new(ARGON2ID_AES_GCM, password, salt)?;
+    let mut aad = stream_aad(&cipher, nonce);
+
+    let mut out = salt.to_vec();
+    out.push(ARGON2ID_AES_GCM);
+    out.extend(nonce);
+
+    let fragments = data.chunks(FRAGMENT_LEN).collect::<Vec<_>>();
+    let count = fragments.len().max(1);
+    for seq in 1..=count {
+        if seq == count {
+            aad[0] = 0x80;
+        }
+        let fragment = fragments.get(seq - 1).copied().unwrap_or_default();
+        out.extend(cipher.seal(&fragment_nonce(nonce, seq as u32), &aad, fragment));
+    }
+
+    Ok(out)
+}
+
+/// Decrypt an admin response payload with a password
+pub fn decrypt_data(password: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if data.len() < SALT_LEN + 1 + NONCE_LEN + TAG_LEN {
+        return Err(MadminError::TooShort.into());
+    }
+
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (id, data) = data.split_at(1);
+    let (nonce, data) = data.split_at(NONCE_LEN);
+
+    let cipher = Cipher::new(id[0], password, salt)?;
+    let mut aad = stream_aad(&cipher, nonce);
+
+    let fragments = data.chunks(FRAGMENT_LEN + TAG_LEN).collect::<Vec<_>>();
+    let mut out = Vec::with_capacity(data.len());
+    for (i, fragment) in fragments.iter().enumerate() {
+        if i + 1 == fragments.len() {
+            aad[0] = 0x80;
+        }
+        out.extend(cipher.open(&fragment_nonce(nonce, i as u32 + 1), &aad, fragment)?);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::madmin::{decrypt_data, encrypt_data, encrypt_with, FRAGMENT_LEN};
+
+    #[test]
+    fn encrypt_decrypt() {
+        let data = br#"{"secretKey":"secret","status":"enabled"}"#;
+        let encrypted = encrypt_data("password", data).unwrap();
+        assert_eq!(decrypt_data("password", &encrypted).unwrap(), data);
+        assert!(decrypt_data("other", &encrypted).is_err());
+    }
+
+    #[test]
+    fn encrypt_multiple_fragments() {
+        let data = (0..FRAGMENT_LEN * 2 + 10)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let encrypted = encrypt_with("password", &data, &[1; 32], &[2; 8]).unwrap();
+        // Salt, algorithm, nonce and a tag per fragment
+        assert_eq!(encrypted.len(), 32 + 1 + 8 + data.len() + 3 * 16);
+        assert_eq!(decrypt_data("password", &encrypted).unwrap(), data);
+
+        // Fragments can not be removed
+        let truncated = &encrypted[..encrypted.len() - 10 - 16];
+        assert!(decrypt_data("password", truncated).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! # Operator metrics
+//!
+//! Metrics are exposed in the Prometheus text format
+
+use std::sync::LazyLock;
+
+use prometheus::{Encoder, IntCounterVec, Opts, Registry, TextEncoder};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+/// The number of bucket settings found to differ from their desired configuration
+pub static BUCKET_DRIFT: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let counter = IntCounterVec::new(
+        Opts::new(
+            "minio_operator_bucket_drift_total",
+            "Bucket settings found to differ from their desired configuration",
+        ),
+        &["namespace", "bucket", "field", "action"],
+    )
+    .unwrap();
+    REGISTRY.register(Box::new(counter.clone())).unwrap();
+    counter
+});
+
+/// Get the current value of all the metrics, in the Prometheus text format
+pub fn encode() -> anyhow::Result<String> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Serve the metrics over HTTP. Any request receives the current value of the metrics
+pub async fn serve(addr: &str) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Metrics are available on http://{addr}/metrics");
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            // The content of the request does not matter
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+
+            let res = match encode() {
+                Ok(body) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                ),
+                Err(e) => {
+                    log::error!("Failed to encode metrics! {e}");
+                    "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+                }
+            };
+
+            if let Err(e) = stream.write_all(res.as_bytes()).await {
+                log::debug!("Failed to send metrics! {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::metrics::{encode, BUCKET_DRIFT};
+
+    #[test]
+    fn encode_drift_metric() {
+        BUCKET_DRIFT
+            .with_label_values(&["default", "mybucket", "quota", "corrected"])
+            .inc();
+        let metrics = encode().unwrap();
+        assert!(metrics.contains("minio_operator_bucket_drift_total"));
+        assert!(metrics.contains("field=\"quota\""));
+    }
+}
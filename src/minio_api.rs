@@ -0,0 +1,1097 @@
+//! # Native Minio client
+//!
+//! Talks directly to the S3 and admin APIs of Minio, without the `mc` command line client
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::Engine;
+use k8s_openapi::jiff::Timestamp;
+use md5::{Digest, Md5};
+use quick_xml::escape::escape;
+use reqwest::{Method, Url};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use crate::constants::MINIO_REGION;
+use crate::crd::{BucketRetention, RetentionType};
+use crate::madmin::{decrypt_data, encrypt_data};
+use crate::minio::{
+    MinioAdminInfo, MinioBackend, MinioServerInfo, MinioService, MinioTimeoutError, MinioUser,
+};
+use crate::minio_error::is_permanent_code;
+use crate::sigv4::{
+    authorization, canonical_query, sha256_hex, SigningCredentials, SigningRequest,
+};
+
+const MINIO_ADMIN_PREFIX: &str = "/minio/admin/v3";
+const S3_XML_NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+#[derive(thiserror::Error, Debug)]
+enum MinioApiError {
+    #[error("Invalid Minio endpoint: {0}")]
+    InvalidEndpoint(String),
+    #[error("Minio API request failed with status {status}: {code} {message}")]
+    Request {
+        status: u16,
+        code: String,
+        message: String,
+    },
+}
+
+/// Get the code of the error returned by the Minio API, if any
+fn error_code(e: &anyhow::Error) -> Option<&str> {
+    match e.downcast_ref::<MinioApiError>() {
+        Some(MinioApiError::Request { code, .. }) => Some(code),
+        _ => None,
+    }
+}
+
+/// Check if an error returned by the Minio API will happen again if the request is retried
+pub fn is_permanent_api_error(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<MinioApiError>() {
+        Some(MinioApiError::InvalidEndpoint(_)) => true,
+        Some(MinioApiError::Request { code, .. }) => is_permanent_code(code),
+        None => false,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiErrorResponse {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+impl MinioApiError {
+    /// Parse an error returned by the S3 API (in XML) or the admin API (in JSON)
+    fn parse(status: u16, body: &[u8]) -> Self {
+        let body = String::from_utf8_lossy(body);
+        let res = quick_xml::de::from_str::<ApiErrorResponse>(&body)
+            .ok()
+            .or_else(|| serde_json::from_str::<ApiErrorResponse>(&body).ok())
+            .unwrap_or_else(|| ApiErrorResponse {
+                code: String::new(),
+                message: body.to_string(),
+            });
+
+        Self::Request {
+            status,
+            code: res.code,
+            message: res.message,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListAllMyBucketsResult {
+    #[serde(default)]
+    buckets: ListBuckets,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBuckets {
+    #[serde(default)]
+    bucket: Vec<ListBucket>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucket {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListVersionsResult {
+    #[serde(default)]
+    version: Vec<IgnoredAny>,
+    #[serde(default)]
+    delete_marker: Vec<IgnoredAny>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VersioningConfiguration {
+    status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ObjectLockConfiguration {
+    object_lock_enabled: Option<String>,
+    rule: Option<ObjectLockRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ObjectLockRule {
+    default_retention: Option<DefaultRetention>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DefaultRetention {
+    mode: Option<String>,
+    days: Option<usize>,
+    years: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Tagging {
+    #[serde(default)]
+    tag_set: TagSet,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TagSet {
+    #[serde(default)]
+    tag: Vec<Tag>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Tag {
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BucketQuota {
+    #[serde(default)]
+    quota: usize,
+    #[serde(default)]
+    size: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PolicyEntities {
+    #[serde(default)]
+    user_mappings: Vec<UserPolicyEntities>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserPolicyEntities {
+    #[serde(default)]
+    policies: Vec<String>,
+}
+
+/// Get the policy that grants anonymous read access to the objects of a bucket
+fn anonymous_read_policy(bucket: &str) -> serde_json::Value {
+    serde_json::json!({
+        "Version": "2012-10-17",
+        "Statement": [
+            {
+                "Effect": "Allow",
+                "Principal": { "AWS": ["*"] },
+                "Action": ["s3:GetBucketLocation", "s3:ListBucket"],
+                "Resource": [format!("arn:aws:s3:::{bucket}")]
+            },
+            {
+                "Effect": "Allow",
+                "Principal": { "AWS": ["*"] },
+                "Action": ["s3:GetObject"],
+                "Resource": [format!("arn:aws:s3:::{bucket}/*")]
+            }
+        ]
+    })
+}
+
+/// Check if a bucket policy grants anonymous read access to all the objects of a bucket
+fn grants_anonymous_read(bucket: &str, policy: &serde_json::Value) -> bool {
+    // Policy fields can either be a single value or an array of values
+    fn values(v: &serde_json::Value) -> Vec<&str> {
+        match v {
+            serde_json::Value::String(s) => vec![s.as_str()],
+            serde_json::Value::Array(a) => a.iter().filter_map(|v| v.as_str()).collect(),
+            _ => vec![],
+        }
+    }
+
+    let objects = format!("arn:aws:s3:::{bucket}/*");
+    policy["Statement"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|s| {
+            s["Effect"] == "Allow"
+                && (s["Principal"] == "*" || values(&s["Principal"]["AWS"]).contains(&"*"))
+                && values(&s["Action"])
+                    .iter()
+                    .any(|a| *a == "s3:GetObject" || *a == "s3:*")
+                && values(&s["Resource"]).contains(&objects.as_str())
+        })
+}
+
+/// A client of the S3 and admin APIs of a Minio instance
+#[derive(Debug, Clone)]
+pub struct MinioApiClient {
+    endpoint: Url,
+    access_key: String,
+    secret_key: String,
+    timeout: Duration,
+    http: reqwest::Client,
+}
+
+impl MinioApiClient {
+    pub fn new(service: &MinioService) -> anyhow::Result<Self> {
+        let endpoint = Url::parse(&service.hostname)
+            .map_err(|_| MinioApiError::InvalidEndpoint(service.hostname.clone()))?;
+        if endpoint.host_str().is_none() {
+            return Err(MinioApiError::InvalidEndpoint(service.hostname.clone()).into());
+        }
+
+        Ok(Self {
+            endpoint,
+            access_key: service.access_key.clone(),
+            secret_key: service.secret_key.clone(),
+            timeout: service.timeout,
+            http: service.tls.http_client(service.timeout)?,
+        })
+    }
+
+    /// Send a signed request, and get the body of the response
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>> {
+        log::debug!("Minio API request {method} {path} {query:?}");
+
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{port}", self.endpoint.host_str().unwrap_or_default()),
+            None => self.endpoint.host_str().unwrap_or_default().to_string(),
+        };
+        let amz_date = Timestamp::now().strftime("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = sha256_hex(&body);
+
+        let mut headers = BTreeMap::from([
+            ("host".to_string(), host),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ]);
+        for (name, value) in extra_headers {
+            headers.insert(name.to_lowercase(), value.clone());
+        }
+
+        let auth = authorization(
+            &SigningRequest {
+                method: method.as_str(),
+                path,
+                query,
+                headers: &headers,
+                payload_hash: &payload_hash,
+            },
+            &SigningCredentials {
+                access_key: &self.access_key,
+                secret_key: &self.secret_key,
+                region: MINIO_REGION,
+            },
+            &amz_date,
+        );
+
+        let query_string = canonical_query(query);
+        let mut url = self.endpoint.clone();
+        url.set_path(path);
+        url.set_query((!query.is_empty()).then_some(query_string.as_str()));
+
+        let mut req = self
+            .http
+            .request(method, url)
+            .header("authorization", auth)
+            .body(body);
+        for (name, value) in headers.iter().filter(|(n, _)| *n != "host") {
+            req = req.header(name, value);
+        }
+
+        let timeout = |e: reqwest::Error| -> anyhow::Error {
+            match e.is_timeout() {
+                true => MinioTimeoutError(self.timeout).into(),
+                false => e.into(),
+            }
+        };
+        let res = req.send().await.map_err(timeout)?;
+        let status = res.status();
+        let body = res.bytes().await.map_err(timeout)?.to_vec();
+        if !status.is_success() {
+            return Err(MinioApiError::parse(status.as_u16(), &body).into());
+        }
+
+        Ok(body)
+    }
+
+    /// Send a request to the S3 API, with an optional XML or JSON payload
+    async fn s3(
+        &self,
+        method: Method,
+        bucket: &str,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: Option<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut headers = extra_headers.to_vec();
+        let body = body.map(|b| b.into_bytes()).unwrap_or_default();
+        if !body.is_empty() {
+            // Some S3 operations require the MD5 hash of the payload
+            headers.push((
+                "content-md5",
+                base64::engine::general_purpose::STANDARD.encode(Md5::digest(&body)),
+            ));
+        }
+
+        self.send(method, &format!("/{bucket}"), query, &headers, body)
+            .await
+    }
+
+    /// Send a request to the admin API
+    async fn admin(
+        &self,
+        method: Method,
+        api: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.send(
+            method,
+            &format!("{MINIO_ADMIN_PREFIX}/{api}"),
+            query,
+            &[],
+            body,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl MinioBackend for MinioApiClient {
+    async fn is_ready(&self) -> bool {
+        let url = self.endpoint.join("minio/health/live");
+        match url {
+            Ok(url) => matches!(self.http.get(url).send().await, Ok(r) if r.status() == 200),
+            Err(_) => false,
+        }
+    }
+
+    /// Get information about the Minio server (version, drives, capacity)
+    async fn server_info(&self) -> anyhow::Result<MinioServerInfo> {
+        let res = self.admin(Method::GET, "info", &[], vec![]).await?;
+        Ok(serde_json::from_slice::<MinioAdminInfo>(&res)?.into())
+    }
+
+    /// Get the names of the buckets
+    async fn buckets_list(&self) -> anyhow::Result<Vec<String>> {
+        let res = self.send(Method::GET, "/", &[], &[], vec![]).await?;
+        let res: ListAllMyBucketsResult = quick_xml::de::from_reader(res.as_slice())?;
+        Ok(res.buckets.bucket.into_iter().map(|b| b.name).collect())
+    }
+
+    /// Create a bucket, if it does not exist yet
+    async fn bucket_create(&self, bucket: &str, lock: bool) -> anyhow::Result<()> {
+        let headers = match lock {
+            true => vec![("x-amz-bucket-object-lock-enabled", "true".to_string())],
+            false => vec![],
+        };
+
+        match self.s3(Method::PUT, bucket, &[], &headers, None).await {
+            Err(e) if error_code(&e) == Some("BucketAlreadyOwnedByYou") => Ok(()),
+            res => res.map(|_| ()),
+        }
+    }
+
+    /// Check if a bucket does not contain any object, including old versions of objects
+    async fn bucket_is_empty(&self, bucket: &str) -> anyhow::Result<bool> {
+        let res = self
+            .s3(
+                Method::GET,
+                bucket,
+                &[("versions", ""), ("max-keys", "1")],
+                &[],
+                None,
+            )
+            .await?;
+        let res: ListVersionsResult = quick_xml::de::from_reader(res.as_slice())?;
+        Ok(res.version.is_empty() && res.delete_marker.is_empty())
+    }
+
+    /// Remove a bucket. Unless `force` is set, the bucket must be empty
+    async fn bucket_remove(&self, bucket: &str, force: bool) -> anyhow::Result<()> {
+        if !self.bucket_exists(bucket).await? {
+            return Ok(());
+        }
+
+        let headers = match force {
+            true => vec![("x-minio-force-delete", "true".to_string())],
+            false => vec![],
+        };
+        self.s3(Method::DELETE, bucket, &[], &headers, None).await?;
+        Ok(())
+    }
+
+    /// Set bucket versioning
+    async fn bucket_set_versioning(&self, bucket: &str, enable: bool) -> anyhow::Result<()> {
+        let status = match enable {
+            true => "Enabled",
+            false => "Suspended",
+        };
+        let body = format!(
+            "<VersioningConfiguration xmlns=\"{S3_XML_NAMESPACE}\"><Status>{status}</Status></VersioningConfiguration>"
+        );
+        self.s3(Method::PUT, bucket, &[("versioning", "")], &[], Some(body))
+            .await?;
+        Ok(())
+    }
+
+    /// Get current bucket versioning status
+    async fn bucket_get_versioning(&self, bucket: &str) -> anyhow::Result<bool> {
+        let res = self
+            .s3(Method::GET, bucket, &[("versioning", "")], &[], None)
+            .await?;
+        let res: VersioningConfiguration = quick_xml::de::from_reader(res.as_slice())?;
+        Ok(res.status.as_deref() == Some("Enabled"))
+    }
+
+    /// Set bucket anonymous access
+    async fn bucket_set_anonymous_access(&self, bucket: &str, access: bool) -> anyhow::Result<()> {
+        if access {
+            let policy = anonymous_read_policy(bucket).to_string();
+            self.s3(Method::PUT, bucket, &[("policy", "")], &[], Some(policy))
+                .await?;
+            return Ok(());
+        }
+
+        match self
+            .s3(Method::DELETE, bucket, &[("policy", "")], &[], None)
+            .await
+        {
+            Err(e) if error_code(&e) == Some("NoSuchBucketPolicy") => Ok(()),
+            res => res.map(|_| ()),
+        }
+    }
+
+    /// Get current bucket anonymous access status
+    async fn bucket_get_anonymous_access(&self, bucket: &str) -> anyhow::Result<bool> {
+        match self
+            .s3(Method::GET, bucket, &[("policy", "")], &[], None)
+            .await
+        {
+            Ok(policy) => Ok(grants_anonymous_read(
+                bucket,
+                &serde_json::from_slice(&policy)?,
+            )),
+            Err(e) if error_code(&e) == Some("NoSuchBucketPolicy") => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Set bucket quota, in bytes
+    async fn bucket_set_quota(&self, bucket: &str, quota: Option<usize>) -> anyhow::Result<()> {
+        let body = match quota {
+            Some(quota) => {
+                serde_json::json!({ "quota": quota, "size": quota, "quotatype": "hard" })
+            }
+            None => serde_json::json!({ "quota": 0, "size": 0 }),
+        };
+        self.admin(
+            Method::PUT,
+            "set-bucket-quota",
+            &[("bucket", bucket)],
+            body.to_string().into_bytes(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Get current bucket quota, in bytes
+    async fn bucket_get_quota(&self, bucket: &str) -> anyhow::Result<Option<usize>> {
+        let res = match self
+            .admin(
+                Method::GET,
+                "get-bucket-quota",
+                &[("bucket", bucket)],
+                vec![],
+            )
+            .await
+        {
+            Ok(res) => res,
+            Err(e) if error_code(&e) == Some("XMinioAdminNoSuchQuotaConfiguration") => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let quota: BucketQuota = serde_json::from_slice(&res)?;
+        Ok(Some(quota.size.max(quota.quota)).filter(|q| *q > 0))
+    }
+
+    /// Set bucket default retention policy
+    async fn bucket_set_default_retention(
+        &self,
+        bucket: &str,
+        retention: Option<BucketRetention>,
+    ) -> anyhow::Result<()> {
+        let rule = match retention {
+            Some(retention) => format!(
+                "<Rule><DefaultRetention><Mode>{}</Mode><Days>{}</Days></DefaultRetention></Rule>",
+                match retention.r#type {
+                    RetentionType::Compliance => "COMPLIANCE",
+                    RetentionType::Governance => "GOVERNANCE",
+                },
+                retention.validity
+            ),
+            None => String::new(),
+        };
+        let body = format!(
+            "<ObjectLockConfiguration xmlns=\"{S3_XML_NAMESPACE}\"><ObjectLockEnabled>Enabled</ObjectLockEnabled>{rule}</ObjectLockConfiguration>"
+        );
+        self.s3(Method::PUT, bucket, &[("object-lock", "")], &[], Some(body))
+            .await?;
+        Ok(())
+    }
+
+    /// Get bucket default retention policy
+    async fn bucket_get_default_retention(
+        &self,
+        bucket: &str,
+    ) -> anyhow::Result<Option<BucketRetention>> {
+        let res = match self
+            .s3(Method::GET, bucket, &[("object-lock", "")], &[], None)
+            .await
+        {
+            Ok(res) => res,
+            Err(e) if error_code(&e) == Some("ObjectLockConfigurationNotFoundError") => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let res: ObjectLockConfiguration = quick_xml::de::from_reader(res.as_slice())?;
+        if res.object_lock_enabled.as_deref() != Some("Enabled") {
+            return Ok(None);
+        }
+
+        let Some(retention) = res.rule.and_then(|r| r.default_retention) else {
+            return Ok(None);
+        };
+        let validity = match (retention.days, retention.years) {
+            (Some(days), _) => days,
+            (None, Some(years)) => years * 365,
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(BucketRetention {
+            validity,
+            r#type: match retention.mode.unwrap_or_default().to_lowercase().as_str() {
+                "governance" => RetentionType::Governance,
+                "compliance" => RetentionType::Compliance,
+                o => {
+                    log::error!("Unknown retention type: {o}");
+                    return Ok(None);
+                }
+            },
+        }))
+    }
+
+    /// Set the tags of a bucket, replacing its current tags
+    async fn bucket_set_tags(
+        &self,
+        bucket: &str,
+        tags: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        if tags.is_empty() {
+            self.s3(Method::DELETE, bucket, &[("tagging", "")], &[], None)
+                .await?;
+            return Ok(());
+        }
+
+        let tags = tags
+            .iter()
+            .map(|(k, v)| {
+                format!(
+                    "<Tag><Key>{}</Key><Value>{}</Value></Tag>",
+                    escape(k),
+                    escape(v)
+                )
+            })
+            .collect::<String>();
+        let body =
+            format!("<Tagging xmlns=\"{S3_XML_NAMESPACE}\"><TagSet>{tags}</TagSet></Tagging>");
+        self.s3(Method::PUT, bucket, &[("tagging", "")], &[], Some(body))
+            .await?;
+        Ok(())
+    }
+
+    /// Get the tags of a bucket
+    async fn bucket_get_tags(&self, bucket: &str) -> anyhow::Result<BTreeMap<String, String>> {
+        let res = match self
+            .s3(Method::GET, bucket, &[("tagging", "")], &[], None)
+            .await
+        {
+            Ok(res) => res,
+            Err(e) if error_code(&e) == Some("NoSuchTagSet") => return Ok(BTreeMap::new()),
+            Err(e) => return Err(e),
+        };
+
+        let res: Tagging = quick_xml::de::from_reader(res.as_slice())?;
+        Ok(res
+            .tag_set
+            .tag
+            .into_iter()
+            .map(|t| (t.key, t.value))
+            .collect())
+    }
+
+    /// Apply a bucket policy
+    async fn policy_apply(&self, name: &str, content: &str) -> anyhow::Result<()> {
+        self.admin(
+            Method::PUT,
+            "add-canned-policy",
+            &[("name", name)],
+            content.as_bytes().to_vec(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Get the list of existing policies
+    async fn policy_list(&self) -> anyhow::Result<Vec<String>> {
+        let res = self
+            .admin(Method::GET, "list-canned-policies", &[], vec![])
+            .await?;
+        let policies: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&res)?;
+        Ok(policies.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Get the content of a given policy
+    async fn policy_content(&self, name: &str) -> anyhow::Result<String> {
+        let res = self
+            .admin(Method::GET, "info-canned-policy", &[("name", name)], vec![])
+            .await?;
+        Ok(serde_json::from_slice::<serde_json::Value>(&res)?.to_string())
+    }
+
+    /// Remove a policy, if it exists
+    async fn policy_remove(&self, name: &str) -> anyhow::Result<()> {
+        if !self.policy_list().await?.iter().any(|p| p == name) {
+            return Ok(());
+        }
+
+        self.admin(
+            Method::DELETE,
+            "remove-canned-policy",
+            &[("name", name)],
+            vec![],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Apply a user
+    async fn user_apply(&self, user: &MinioUser) -> anyhow::Result<()> {
+        let body = serde_json::json!({ "secretKey": user.password, "status": "enabled" });
+        self.admin(
+            Method::PUT,
+            "add-user",
+            &[("accessKey", &user.username)],
+            encrypt_data(&self.secret_key, body.to_string().as_bytes())?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Get the list of users
+    async fn user_list(&self) -> anyhow::Result<Vec<String>> {
+        let res = self.admin(Method::GET, "list-users", &[], vec![]).await?;
+        let users: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&decrypt_data(&self.secret_key, &res)?)?;
+        Ok(users.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Remove a user, if it exists
+    async fn user_remove(&self, username: &str) -> anyhow::Result<()> {
+        if !self.user_list().await?.iter().any(|u| u == username) {
+            return Ok(());
+        }
+
+        self.admin(
+            Method::DELETE,
+            "remove-user",
+            &[("accessKey", username)],
+            vec![],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Attach a user to a policy
+    async fn policy_attach_user(&self, user: &MinioUser, policy: &str) -> anyhow::Result<()> {
+        // Attaching a policy that is already attached fails
+        if self
+            .policy_attach_get_user_list(user)
+            .await?
+            .contains(&policy.to_string())
+        {
+            return Ok(());
+        }
+
+        let body = serde_json::json!({ "policies": [policy], "user": user.username });
+        self.admin(
+            Method::POST,
+            "idp/builtin/policy/attach",
+            &[],
+            encrypt_data(&self.secret_key, body.to_string().as_bytes())?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Get the list of policies attached to a user
+    async fn policy_attach_get_user_list(&self, user: &MinioUser) -> anyhow::Result<Vec<String>> {
+        let res = self
+            .admin(
+                Method::GET,
+                "idp/builtin/policy-entities",
+                &[("user", &user.username)],
+                vec![],
+            )
+            .await?;
+        let entities: PolicyEntities = serde_json::from_slice(&res)?;
+        Ok(entities
+            .user_mappings
+            .into_iter()
+            .next()
+            .map(|m| m.policies)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::crd::{BucketRetention, MinioBucketSpec, RetentionType};
+    use crate::minio::{
+        McConfig, MinioBackend, MinioBackendKind, MinioService, MinioTimeoutError, MinioUser,
+    };
+    use crate::minio_api::{
+        anonymous_read_policy, error_code, grants_anonymous_read, is_permanent_api_error,
+        ListAllMyBucketsResult, ListVersionsResult, MinioApiClient, MinioApiError,
+        ObjectLockConfiguration,
+    };
+    use crate::minio_test_server::MinioTestServer;
+
+    const TEST_BUCKET_NAME: &str = "mybucket";
+    const TEST_POLICY_NAME: &str = "mypolicy";
+
+    #[test]
+    fn parse_errors() {
+        let e: anyhow::Error = MinioApiError::parse(
+            404,
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>NoSuchBucketPolicy</Code><Message>The bucket policy does not exist</Message><BucketName>mybucket</BucketName></Error>"#,
+        )
+        .into();
+        assert_eq!(error_code(&e), Some("NoSuchBucketPolicy"));
+        assert!(!is_permanent_api_error(&e));
+
+        let e: anyhow::Error = MinioApiError::parse(
+            403,
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessDenied</Code><Message>Access Denied.</Message></Error>"#,
+        )
+        .into();
+        assert!(is_permanent_api_error(&e));
+
+        let e: anyhow::Error = MinioApiError::parse(
+            404,
+            br#"{"Code":"XMinioAdminNoSuchQuotaConfiguration","Message":"The quota configuration does not exist"}"#,
+        )
+        .into();
+        assert_eq!(error_code(&e), Some("XMinioAdminNoSuchQuotaConfiguration"));
+
+        let e: anyhow::Error = MinioApiError::parse(502, b"Bad gateway").into();
+        assert_eq!(error_code(&e), Some(""));
+        assert!(e.to_string().contains("Bad gateway"));
+        assert!(!is_permanent_api_error(&e));
+    }
+
+    #[test]
+    fn parse_buckets_list() {
+        let res: ListAllMyBucketsResult = quick_xml::de::from_str(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListAllMyBucketsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Owner><ID>02d6176db174dc93cb1b899f7c6078f08654445fe8cf1b6ce98d8855f66bdbf4</ID><DisplayName>minio</DisplayName></Owner><Buckets><Bucket><Name>first</Name><CreationDate>2024-01-01T00:00:00.000Z</CreationDate></Bucket><Bucket><Name>second</Name><CreationDate>2024-01-01T00:00:00.000Z</CreationDate></Bucket></Buckets></ListAllMyBucketsResult>"#,
+        )
+        .unwrap();
+        let names = res
+            .buckets
+            .bucket
+            .iter()
+            .map(|b| b.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["first", "second"]);
+
+        let res: ListAllMyBucketsResult = quick_xml::de::from_str(
+            r#"<ListAllMyBucketsResult><Owner></Owner><Buckets></Buckets></ListAllMyBucketsResult>"#,
+        )
+        .unwrap();
+        assert!(res.buckets.bucket.is_empty());
+    }
+
+    #[test]
+    fn parse_versions_list() {
+        let res: ListVersionsResult = quick_xml::de::from_str(
+            r#"<ListVersionsResult><Name>b</Name><MaxKeys>1</MaxKeys><IsTruncated>false</IsTruncated></ListVersionsResult>"#,
+        )
+        .unwrap();
+        assert!(res.version.is_empty() && res.delete_marker.is_empty());
+
+        let res: ListVersionsResult = quick_xml::de::from_str(
+            r#"<ListVersionsResult><Name>b</Name><DeleteMarker><Key>a</Key><VersionId>1</VersionId></DeleteMarker></ListVersionsResult>"#,
+        )
+        .unwrap();
+        assert_eq!(res.delete_marker.len(), 1);
+    }
+
+    #[test]
+    fn parse_object_lock() {
+        let res: ObjectLockConfiguration = quick_xml::de::from_str(
+            r#"<ObjectLockConfiguration><ObjectLockEnabled>Enabled</ObjectLockEnabled><Rule><DefaultRetention><Mode>GOVERNANCE</Mode><Days>10</Days></DefaultRetention></Rule></ObjectLockConfiguration>"#,
+        )
+        .unwrap();
+        let retention = res.rule.unwrap().default_retention.unwrap();
+        assert_eq!(retention.mode.as_deref(), Some("GOVERNANCE"));
+        assert_eq!(retention.days, Some(10));
+    }
+
+    #[test]
+    fn anonymous_read_access() {
+        assert!(grants_anonymous_read(
+            "mybucket",
+            &anonymous_read_policy("mybucket")
+        ));
+        assert!(!grants_anonymous_read(
+            "other",
+            &anonymous_read_policy("mybucket")
+        ));
+
+        let policy = serde_json::json!({
+            "Version": "2012-10-17",
+            "Statement": [{
+                "Effect": "Allow",
+                "Principal": "*",
+                "Action": "s3:*",
+                "Resource": "arn:aws:s3:::mybucket/*"
+            }]
+        });
+        assert!(grants_anonymous_read("mybucket", &policy));
+
+        let policy = serde_json::json!({
+            "Version": "2012-10-17",
+            "Statement": [{
+                "Effect": "Allow",
+                "Principal": { "AWS": ["*"] },
+                "Action": ["s3:PutObject"],
+                "Resource": ["arn:aws:s3:::mybucket/*"]
+            }]
+        });
+        assert!(!grants_anonymous_read("mybucket", &policy));
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        // A server that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut conns = vec![];
+            while let Ok((conn, _)) = listener.accept().await {
+                conns.push(conn);
+            }
+        });
+
+        let client = MinioApiClient::new(&MinioService {
+            hostname: format!("http://{addr}"),
+            access_key: "access".to_string(),
+            secret_key: "secret".to_string(),
+            backend: MinioBackendKind::Native,
+            timeout: Duration::from_millis(200),
+            tls: Default::default(),
+            mc_config: Arc::new(McConfig::new("", &Default::default()).unwrap()),
+        })
+        .unwrap();
+
+        let err = client.buckets_list().await.unwrap_err();
+        assert!(err.is::<MinioTimeoutError>(), "{err}");
+        assert!(!client.is_ready().await);
+    }
+
+    #[tokio::test]
+    async fn native_bucket_settings() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let srv = MinioTestServer::start().await.unwrap();
+        let service = srv.as_native_client();
+        let spec = MinioBucketSpec {
+            name: TEST_BUCKET_NAME.to_string(),
+            anonymous_read_access: true,
+            versioning: true,
+            quota: Some(42300),
+            lock: true,
+            retention: Some(BucketRetention {
+                validity: 10,
+                r#type: RetentionType::Governance,
+            }),
+            ..Default::default()
+        };
+        service.bucket_apply(&spec).await.unwrap();
+
+        assert!(service.bucket_exists(TEST_BUCKET_NAME).await.unwrap());
+        assert!(service
+            .bucket_get_versioning(TEST_BUCKET_NAME)
+            .await
+            .unwrap());
+        assert!(service
+            .bucket_get_anonymous_access(TEST_BUCKET_NAME)
+            .await
+            .unwrap());
+        assert_eq!(
+            service.bucket_get_quota(TEST_BUCKET_NAME).await.unwrap(),
+            Some(42300)
+        );
+        assert_eq!(
+            service
+                .bucket_get_default_retention(TEST_BUCKET_NAME)
+                .await
+                .unwrap(),
+            spec.retention
+        );
+        assert!(service.bucket_plan(&spec).await.unwrap().is_empty());
+
+        assert!(service
+            .bucket_get_tags(TEST_BUCKET_NAME)
+            .await
+            .unwrap()
+            .is_empty());
+        let tags = BTreeMap::from([("owner".to_string(), "team-a/bucket".to_string())]);
+        service
+            .bucket_set_tags(TEST_BUCKET_NAME, &tags)
+            .await
+            .unwrap();
+        assert_eq!(
+            service.bucket_get_tags(TEST_BUCKET_NAME).await.unwrap(),
+            tags
+        );
+
+        // The native client and mc must agree on the settings of the bucket
+        assert_eq!(
+            srv.as_service()
+                .bucket_settings(TEST_BUCKET_NAME, spec.lock)
+                .await
+                .unwrap(),
+            service
+                .bucket_settings(TEST_BUCKET_NAME, spec.lock)
+                .await
+                .unwrap()
+        );
+
+        service
+            .bucket_set_anonymous_access(TEST_BUCKET_NAME, false)
+            .await
+            .unwrap();
+        service
+            .bucket_set_quota(TEST_BUCKET_NAME, None)
+            .await
+            .unwrap();
+        service
+            .bucket_set_default_retention(TEST_BUCKET_NAME, None)
+            .await
+            .unwrap();
+        assert!(!service
+            .bucket_get_anonymous_access(TEST_BUCKET_NAME)
+            .await
+            .unwrap());
+        assert_eq!(
+            service.bucket_get_quota(TEST_BUCKET_NAME).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            service
+                .bucket_get_default_retention(TEST_BUCKET_NAME)
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert!(service.bucket_is_empty(TEST_BUCKET_NAME).await.unwrap());
+        service
+            .bucket_remove(TEST_BUCKET_NAME, false)
+            .await
+            .unwrap();
+        assert!(!service.bucket_exists(TEST_BUCKET_NAME).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn native_users_and_policies() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let srv = MinioTestServer::start().await.unwrap();
+        let service = srv.as_native_client();
+        let user = MinioUser::gen_random("myuser");
+
+        service.user_apply(&user).await.unwrap();
+        assert!(service.user_list().await.unwrap().contains(&user.username));
+
+        service
+            .policy_apply(
+                TEST_POLICY_NAME,
+                &include_str!("policy_template.json").replace("{{ bucket }}", TEST_BUCKET_NAME),
+            )
+            .await
+            .unwrap();
+        assert!(service
+            .policy_list()
+            .await
+            .unwrap()
+            .contains(&TEST_POLICY_NAME.to_string()));
+
+        service
+            .policy_attach_user(&user, TEST_POLICY_NAME)
+            .await
+            .unwrap();
+        assert_eq!(
+            service.policy_attach_get_user_list(&user).await.unwrap(),
+            vec![TEST_POLICY_NAME.to_string()]
+        );
+
+        service.user_remove(&user.username).await.unwrap();
+        service.policy_remove(TEST_POLICY_NAME).await.unwrap();
+        assert!(!service.user_list().await.unwrap().contains(&user.username));
+        assert!(!service
+            .policy_list()
+            .await
+            .unwrap()
+            .contains(&TEST_POLICY_NAME.to_string()));
+    }
+
+    #[tokio::test]
+    async fn native_server_info() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let srv = MinioTestServer::start().await.unwrap();
+        let info = srv.as_native_client().server_info().await.unwrap();
+        assert!(info.version.is_some());
+        assert_eq!(info.drives_offline, 0);
+    }
+}
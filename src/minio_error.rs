@@ -0,0 +1,223 @@
+//! # Minio errors
+//!
+//! Typed errors returned by Minio instances, and their classification as transient or
+//! permanent errors
+
+use std::fmt::{Display, Formatter};
+
+use serde::Deserialize;
+
+use crate::minio::MinioTimeoutError;
+
+/// The error codes of Minio that indicate a configuration that must be fixed before the
+/// operation can succeed. Any other error may be caused by a temporary condition
+const PERMANENT_ERROR_CODES: [&str; 13] = [
+    "AccessDenied",
+    "InvalidAccessKeyId",
+    "SignatureDoesNotMatch",
+    "InvalidBucketName",
+    "BucketAlreadyExists",
+    "InvalidArgument",
+    "MalformedXML",
+    "InvalidRequest",
+    "NotImplemented",
+    "ObjectLockConfigurationNotFoundError",
+    "InvalidBucketState",
+    "XMinioAdminNoSuchUser",
+    "XMinioAdminNoSuchPolicy",
+];
+
+/// Check if an error code of Minio indicates a configuration that must be fixed
+pub fn is_permanent_code(code: &str) -> bool {
+    PERMANENT_ERROR_CODES.contains(&code)
+}
+
+/// An error reported by the `mc` command line client
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub struct McError {
+    /// The operation that failed
+    pub operation: String,
+    /// The bucket targeted by the operation, if any
+    pub bucket: Option<String>,
+    /// The error code returned by Minio, if any
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl Display for McError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to {}", self.operation)?;
+        if let Some(bucket) = &self.bucket {
+            write!(f, " on bucket {bucket}")?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(code) = &self.code {
+            write!(f, " ({code})")?;
+        }
+        Ok(())
+    }
+}
+
+/// An error object printed by mc in JSON mode
+#[derive(Debug, Deserialize)]
+struct McErrorResult {
+    #[serde(default)]
+    status: String,
+    error: Option<McErrorBody>,
+}
+
+#[derive(Debug, Deserialize)]
+struct McErrorBody {
+    #[serde(default)]
+    message: String,
+    cause: Option<McErrorCause>,
+}
+
+#[derive(Debug, Deserialize)]
+struct McErrorCause {
+    message: Option<String>,
+    error: Option<McErrorDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct McErrorDetails {
+    code: Option<String>,
+    bucket_name: Option<String>,
+}
+
+impl McError {
+    pub fn new(operation: &str, bucket: Option<&str>, message: &str) -> Self {
+        Self {
+            operation: operation.to_string(),
+            bucket: bucket.map(str::to_string),
+            code: None,
+            message: message.to_string(),
+        }
+    }
+
+    /// Extract the error printed by mc in one of its outputs, if any. Errors are printed as
+    /// indented JSON objects
+    pub fn parse(operation: &str, bucket: Option<&str>, output: &str) -> Option<Self> {
+        let body = serde_json::Deserializer::from_str(output)
+            .into_iter::<McErrorResult>()
+            .map_while(Result::ok)
+            .filter(|r| r.status == "error")
+            .find_map(|r| r.error)?;
+
+        let cause = body.cause.as_ref();
+        let details = cause.and_then(|c| c.error.as_ref());
+        let message = match cause.and_then(|c| c.message.as_deref()) {
+            Some(cause) if !cause.is_empty() && cause != body.message => {
+                format!("{} {cause}", body.message)
+            }
+            _ => body.message.clone(),
+        };
+
+        Some(Self {
+            operation: operation.to_string(),
+            bucket: details
+                .and_then(|d| d.bucket_name.clone())
+                .filter(|b| !b.is_empty())
+                .or_else(|| bucket.map(str::to_string)),
+            code: details
+                .and_then(|d| d.code.clone())
+                .filter(|c| !c.is_empty()),
+            message,
+        })
+    }
+
+    /// Check if the error will happen again if the operation is retried without changing the
+    /// configuration. Errors without a known code may be caused by a temporary condition
+    pub fn is_permanent(&self) -> bool {
+        self.code.as_deref().is_some_and(is_permanent_code)
+    }
+}
+
+/// Check if an error returned by a Minio instance will happen again if the operation is
+/// retried without changing the configuration. Errors that do not come from Minio, or whose
+/// cause is unknown, are never considered permanent
+pub fn is_permanent_error(e: &anyhow::Error) -> bool {
+    if e.is::<MinioTimeoutError>() {
+        return false;
+    }
+
+    if let Some(e) = e.downcast_ref::<McError>() {
+        return e.is_permanent();
+    }
+
+    crate::minio_api::is_permanent_api_error(e)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::minio::MinioTimeoutError;
+    use crate::minio_error::{is_permanent_error, McError};
+
+    #[test]
+    fn parse_mc_error() {
+        let output = r#"{"status":"success"}
+{
+ "status": "error",
+ "error": {
+  "message": "Unable to make bucket `managedminioinst/mybucket`.",
+  "cause": {
+   "message": "The requested bucket name is not available. The bucket namespace is shared by all users of the system. Please select a different name and try again.",
+   "error": {
+    "Code": "BucketAlreadyExists",
+    "Message": "The requested bucket name is not available. The bucket namespace is shared by all users of the system. Please select a different name and try again.",
+    "BucketName": "mybucket",
+    "Resource": "/mybucket"
+   }
+  },
+  "type": "error"
+ }
+}"#;
+
+        let e = McError::parse("mb", None, output).unwrap();
+        assert_eq!(e.bucket.as_deref(), Some("mybucket"));
+        assert_eq!(e.code.as_deref(), Some("BucketAlreadyExists"));
+        assert!(e.message.starts_with("Unable to make bucket"));
+        assert!(e.is_permanent());
+        assert!(is_permanent_error(&e.into()));
+
+        assert!(McError::parse("mb", None, r#"{"status":"success"}"#).is_none());
+        assert!(McError::parse("mb", None, "not json").is_none());
+    }
+
+    #[test]
+    fn classify_errors() {
+        let e = McError::parse(
+            "ls",
+            Some("mybucket"),
+            r#"{"status":"error","error":{"message":"Unable to list.","cause":{"message":"dial tcp 10.0.0.1:9000: connect: connection refused"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(e.bucket.as_deref(), Some("mybucket"));
+        assert_eq!(e.code, None);
+        assert!(!e.is_permanent());
+
+        let e = McError {
+            code: Some("XMinioServerNotInitialized".to_string()),
+            ..McError::new("admin info", None, "Server not initialized")
+        };
+        assert!(!is_permanent_error(&e.into()));
+
+        // Unknown failures, such as an unexpected output of mc, are retried
+        let e = McError::new("admin info", None, "mc exited with exit status: 1");
+        assert!(!is_permanent_error(&e.into()));
+
+        let e = McError {
+            code: Some("InvalidAccessKeyId".to_string()),
+            ..McError::new("ls", None, "The access key does not exist")
+        };
+        assert!(is_permanent_error(&e.into()));
+
+        assert!(!is_permanent_error(
+            &MinioTimeoutError(Duration::from_secs(1)).into()
+        ));
+        assert!(!is_permanent_error(&anyhow::anyhow!("Kubernetes error")));
+    }
+}
@@ -0,0 +1,409 @@
+//! # In-memory Minio backend
+//!
+//! Used for testing only
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::crd::BucketRetention;
+use crate::minio::{BucketSettings, MinioBackend, MinioServerInfo, MinioUser};
+
+#[derive(thiserror::Error, Debug)]
+pub enum FakeMinioError {
+    #[error("Injected failure of operation {0}!")]
+    Injected(&'static str),
+    #[error("Bucket {0} does not exist!")]
+    NoSuchBucket(String),
+    #[error("Bucket {0} is not empty!")]
+    BucketNotEmpty(String),
+    #[error("Object locking is not enabled on bucket {0}!")]
+    LockNotEnabled(String),
+    #[error("Versioning can not be disabled on bucket {0} with object locking!")]
+    LockedVersioning(String),
+    #[error("Policy {0} does not exist!")]
+    NoSuchPolicy(String),
+    #[error("User {0} does not exist!")]
+    NoSuchUser(String),
+}
+
+/// A bucket stored by the fake backend
+#[derive(Debug, Clone, Default)]
+pub struct FakeBucket {
+    pub settings: BucketSettings,
+    pub lock: bool,
+    /// The number of objects stored in the bucket
+    pub objects: usize,
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+pub struct FakeMinioState {
+    pub buckets: BTreeMap<String, FakeBucket>,
+    /// The password of each user
+    pub users: BTreeMap<String, String>,
+    /// The content of each policy
+    pub policies: BTreeMap<String, String>,
+    /// The policies attached to each user
+    pub attachments: BTreeMap<String, BTreeSet<String>>,
+    /// The operations that must fail
+    failing: BTreeSet<&'static str>,
+}
+
+/// A Minio backend that keeps its state in memory, mimicking the errors of a real instance
+#[derive(Debug, Default)]
+pub struct FakeMinio {
+    pub state: Mutex<FakeMinioState>,
+    pub ready: bool,
+}
+
+impl FakeMinio {
+    pub fn new() -> Self {
+        Self {
+            state: Default::default(),
+            ready: true,
+        }
+    }
+
+    /// Make an operation fail, until it is called again with `fail` set to false
+    pub fn fail_on(&self, op: &'static str, fail: bool) {
+        let mut state = self.state.lock().unwrap();
+        match fail {
+            true => state.failing.insert(op),
+            false => state.failing.remove(op),
+        };
+    }
+
+    /// Get the settings of a bucket, if it exists
+    pub fn bucket(&self, name: &str) -> Option<FakeBucket> {
+        self.state.lock().unwrap().buckets.get(name).cloned()
+    }
+
+    /// Run an operation on the state, unless a failure was injected for it
+    fn with_state<R>(
+        &self,
+        op: &'static str,
+        f: impl FnOnce(&mut FakeMinioState) -> Result<R, FakeMinioError>,
+    ) -> anyhow::Result<R> {
+        let mut state = self.state.lock().unwrap();
+        if state.failing.contains(op) {
+            return Err(FakeMinioError::Injected(op).into());
+        }
+        Ok(f(&mut state)?)
+    }
+}
+
+impl FakeMinioState {
+    fn bucket_mut(&mut self, name: &str) -> Result<&mut FakeBucket, FakeMinioError> {
+        self.buckets
+            .get_mut(name)
+            .ok_or_else(|| FakeMinioError::NoSuchBucket(name.to_string()))
+    }
+}
+
+#[async_trait]
+impl MinioBackend for FakeMinio {
+    async fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    async fn server_info(&self) -> anyhow::Result<MinioServerInfo> {
+        self.with_state("server_info", |_| {
+            Ok(MinioServerInfo {
+                version: Some("fake".to_string()),
+                mode: Some("online".to_string()),
+                drives_online: 1,
+                drives_offline: 0,
+                total_capacity: 0,
+                used_capacity: 0,
+            })
+        })
+    }
+
+    async fn buckets_list(&self) -> anyhow::Result<Vec<String>> {
+        self.with_state("buckets_list", |s| Ok(s.buckets.keys().cloned().collect()))
+    }
+
+    async fn bucket_create(&self, bucket: &str, lock: bool) -> anyhow::Result<()> {
+        self.with_state("bucket_create", |s| {
+            s.buckets.entry(bucket.to_string()).or_insert(FakeBucket {
+                settings: BucketSettings {
+                    versioning: lock,
+                    ..Default::default()
+                },
+                lock,
+                objects: 0,
+                tags: BTreeMap::new(),
+            });
+            Ok(())
+        })
+    }
+
+    async fn bucket_is_empty(&self, bucket: &str) -> anyhow::Result<bool> {
+        self.with_state(
+            "bucket_is_empty",
+            |s| Ok(s.bucket_mut(bucket)?.objects == 0),
+        )
+    }
+
+    async fn bucket_remove(&self, bucket: &str, force: bool) -> anyhow::Result<()> {
+        self.with_state("bucket_remove", |s| {
+            match s.buckets.get(bucket) {
+                Some(b) if b.objects > 0 && !force => {
+                    return Err(FakeMinioError::BucketNotEmpty(bucket.to_string()))
+                }
+                _ => s.buckets.remove(bucket),
+            };
+            Ok(())
+        })
+    }
+
+    async fn bucket_set_versioning(&self, bucket: &str, enable: bool) -> anyhow::Result<()> {
+        self.with_state("bucket_set_versioning", |s| {
+            let b = s.bucket_mut(bucket)?;
+            if b.lock && !enable {
+                return Err(FakeMinioError::LockedVersioning(bucket.to_string()));
+            }
+            b.settings.versioning = enable;
+            Ok(())
+        })
+    }
+
+    async fn bucket_get_versioning(&self, bucket_name: &str) -> anyhow::Result<bool> {
+        self.with_state("bucket_get_versioning", |s| {
+            Ok(s.bucket_mut(bucket_name)?.settings.versioning)
+        })
+    }
+
+    async fn bucket_set_anonymous_access(
+        &self,
+        bucket_name: &str,
+        access: bool,
+    ) -> anyhow::Result<()> {
+        self.with_state("bucket_set_anonymous_access", |s| {
+            s.bucket_mut(bucket_name)?.settings.anonymous_read_access = access;
+            Ok(())
+        })
+    }
+
+    async fn bucket_get_anonymous_access(&self, bucket_name: &str) -> anyhow::Result<bool> {
+        self.with_state("bucket_get_anonymous_access", |s| {
+            Ok(s.bucket_mut(bucket_name)?.settings.anonymous_read_access)
+        })
+    }
+
+    async fn bucket_set_quota(&self, bucket: &str, quota: Option<usize>) -> anyhow::Result<()> {
+        self.with_state("bucket_set_quota", |s| {
+            s.bucket_mut(bucket)?.settings.quota = quota;
+            Ok(())
+        })
+    }
+
+    async fn bucket_get_quota(&self, bucket_name: &str) -> anyhow::Result<Option<usize>> {
+        self.with_state("bucket_get_quota", |s| {
+            Ok(s.bucket_mut(bucket_name)?.settings.quota)
+        })
+    }
+
+    async fn bucket_set_default_retention(
+        &self,
+        bucket_name: &str,
+        retention: Option<BucketRetention>,
+    ) -> anyhow::Result<()> {
+        self.with_state("bucket_set_default_retention", |s| {
+            let b = s.bucket_mut(bucket_name)?;
+            if !b.lock {
+                return Err(FakeMinioError::LockNotEnabled(bucket_name.to_string()));
+            }
+            b.settings.retention = retention;
+            Ok(())
+        })
+    }
+
+    async fn bucket_get_default_retention(
+        &self,
+        bucket: &str,
+    ) -> anyhow::Result<Option<BucketRetention>> {
+        self.with_state("bucket_get_default_retention", |s| {
+            Ok(s.bucket_mut(bucket)?.settings.retention)
+        })
+    }
+
+    async fn bucket_set_tags(
+        &self,
+        bucket: &str,
+        tags: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        self.with_state("bucket_set_tags", |s| {
+            s.bucket_mut(bucket)?.tags = tags.clone();
+            Ok(())
+        })
+    }
+
+    async fn bucket_get_tags(&self, bucket: &str) -> anyhow::Result<BTreeMap<String, String>> {
+        self.with_state("bucket_get_tags", |s| {
+            Ok(s.bucket_mut(bucket)?.tags.clone())
+        })
+    }
+
+    async fn policy_apply(&self, name: &str, content: &str) -> anyhow::Result<()> {
+        self.with_state("policy_apply", |s| {
+            s.policies.insert(name.to_string(), content.to_string());
+            Ok(())
+        })
+    }
+
+    async fn policy_list(&self) -> anyhow::Result<Vec<String>> {
+        self.with_state("policy_list", |s| Ok(s.policies.keys().cloned().collect()))
+    }
+
+    async fn policy_content(&self, name: &str) -> anyhow::Result<String> {
+        self.with_state("policy_content", |s| {
+            s.policies
+                .get(name)
+                .cloned()
+                .ok_or_else(|| FakeMinioError::NoSuchPolicy(name.to_string()))
+        })
+    }
+
+    async fn policy_remove(&self, name: &str) -> anyhow::Result<()> {
+        self.with_state("policy_remove", |s| {
+            s.policies.remove(name);
+            s.attachments.values_mut().for_each(|p| {
+                p.remove(name);
+            });
+            Ok(())
+        })
+    }
+
+    async fn user_apply(&self, user: &MinioUser) -> anyhow::Result<()> {
+        self.with_state("user_apply", |s| {
+            s.users
+                .insert(user.username.to_string(), user.password.to_string());
+            Ok(())
+        })
+    }
+
+    async fn user_list(&self) -> anyhow::Result<Vec<String>> {
+        self.with_state("user_list", |s| Ok(s.users.keys().cloned().collect()))
+    }
+
+    async fn user_remove(&self, username: &str) -> anyhow::Result<()> {
+        self.with_state("user_remove", |s| {
+            s.users.remove(username);
+            s.attachments.remove(username);
+            Ok(())
+        })
+    }
+
+    async fn policy_attach_user(&self, user: &MinioUser, policy: &str) -> anyhow::Result<()> {
+        self.with_state("policy_attach_user", |s| {
+            if !s.users.contains_key(&user.username) {
+                return Err(FakeMinioError::NoSuchUser(user.username.to_string()));
+            }
+            if !s.policies.contains_key(policy) {
+                return Err(FakeMinioError::NoSuchPolicy(policy.to_string()));
+            }
+            s.attachments
+                .entry(user.username.to_string())
+                .or_default()
+                .insert(policy.to_string());
+            Ok(())
+        })
+    }
+
+    async fn policy_attach_get_user_list(&self, user: &MinioUser) -> anyhow::Result<Vec<String>> {
+        self.with_state("policy_attach_get_user_list", |s| {
+            if !s.users.contains_key(&user.username) {
+                return Err(FakeMinioError::NoSuchUser(user.username.to_string()));
+            }
+            Ok(s.attachments
+                .get(&user.username)
+                .map(|p| p.iter().cloned().collect())
+                .unwrap_or_default())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crd::{BucketRetention, MinioBucketSpec, RetentionType};
+    use crate::minio::{MinioBackend, MinioUser};
+    use crate::minio_fake::FakeMinio;
+
+    #[tokio::test]
+    async fn fake_mimics_minio_errors() {
+        let minio = FakeMinio::new();
+        let retention = Some(BucketRetention {
+            validity: 1,
+            r#type: RetentionType::Governance,
+        });
+
+        assert!(minio.bucket_set_quota("missing", Some(10)).await.is_err());
+
+        minio.bucket_create("bucket", false).await.unwrap();
+        assert!(minio
+            .bucket_set_default_retention("bucket", retention)
+            .await
+            .is_err());
+
+        minio
+            .state
+            .lock()
+            .unwrap()
+            .buckets
+            .get_mut("bucket")
+            .unwrap()
+            .objects = 1;
+        assert!(minio.bucket_remove("bucket", false).await.is_err());
+        minio.bucket_remove("bucket", true).await.unwrap();
+        assert!(!minio.bucket_exists("bucket").await.unwrap());
+
+        let user = MinioUser::gen_random("bucket");
+        assert!(minio.policy_attach_user(&user, "policy").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fake_applies_bucket_spec() {
+        let minio = FakeMinio::new();
+        let spec = MinioBucketSpec {
+            name: "bucket".to_string(),
+            lock: true,
+            quota: Some(100),
+            retention: Some(BucketRetention {
+                validity: 1,
+                r#type: RetentionType::Compliance,
+            }),
+            ..Default::default()
+        };
+
+        assert!(minio.bucket_apply(&spec).await.unwrap().create);
+        assert!(minio.bucket_plan(&spec).await.unwrap().is_empty());
+
+        minio.fail_on("bucket_get_quota", true);
+        assert!(minio.bucket_plan(&spec).await.is_err());
+        minio.fail_on("bucket_get_quota", false);
+        assert!(minio.bucket_plan(&spec).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn drift_without_lock() {
+        let minio = FakeMinio::new();
+        let spec = MinioBucketSpec {
+            name: "bucket".to_string(),
+            quota: Some(100),
+            ..Default::default()
+        };
+        minio.bucket_apply(&spec).await.unwrap();
+
+        // The retention of buckets without object locking must not be read
+        minio.fail_on("bucket_get_default_retention", true);
+        assert!(minio.bucket_plan(&spec).await.unwrap().is_empty());
+
+        minio.bucket_set_quota("bucket", Some(10)).await.unwrap();
+        let plan = minio.bucket_plan(&spec).await.unwrap();
+        assert!(!plan.create);
+        assert_eq!(plan.changes.len(), 1);
+    }
+}
@@ -2,12 +2,14 @@
 //!
 //! Used for testing only
 
-use crate::minio::MinioService;
+use crate::constants::MINIO_DEFAULT_REQUEST_TIMEOUT;
+use crate::minio::{McConfig, MinioBackend, MinioBackendKind, MinioService};
+use crate::minio_api::MinioApiClient;
 use crate::temp;
 use crate::utils::rand_str;
 use rand::Rng;
-use std::io::ErrorKind;
 use std::process::{Child, Command};
+use std::sync::Arc;
 use std::time::Duration;
 
 pub struct MinioTestServer {
@@ -57,11 +59,7 @@ impl MinioTestServer {
         loop {
             if check_count >= 100 {
                 log::error!("Minio failed to respond properly in time!");
-                return Err(std::io::Error::new(
-                    ErrorKind::Other,
-                    "Minio failed to respond in time!",
-                )
-                .into());
+                return Err(std::io::Error::other("Minio failed to respond in time!").into());
             }
             check_count += 1;
 
@@ -79,12 +77,21 @@ impl MinioTestServer {
         format!("http://127.0.0.1:{}", self.api_port)
     }
 
+    /// Get a native Minio client of this temporary server
+    pub fn as_native_client(&self) -> MinioApiClient {
+        MinioApiClient::new(&self.as_service()).unwrap()
+    }
+
     /// Get a MinioService instance of this temporary server
     pub fn as_service(&self) -> MinioService {
         MinioService {
             hostname: self.base_url(),
             access_key: self.root_user.clone(),
             secret_key: self.root_password.clone(),
+            backend: MinioBackendKind::Mc,
+            timeout: MINIO_DEFAULT_REQUEST_TIMEOUT,
+            tls: Default::default(),
+            mc_config: Arc::new(McConfig::new("", &Default::default()).unwrap()),
         }
     }
 }
@@ -99,6 +106,7 @@ impl Drop for MinioTestServer {
 
 #[cfg(test)]
 mod test {
+    use crate::minio::MinioBackend;
     use crate::minio_test_server::MinioTestServer;
 
     #[tokio::test]
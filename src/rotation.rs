@@ -0,0 +1,208 @@
+//! # Credentials rotation
+//!
+//! Decides when the credentials of buckets must be rotated, and when their previous users
+//! must be removed
+
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::jiff::Timestamp;
+use kube::ResourceExt;
+
+use crate::constants::{DEFAULT_ROTATION_GRACE_PERIOD, ROTATE_CREDENTIALS_ANNOTATION};
+use crate::crd::{MinioBucket, MinioBucketStatus, RetiredUser};
+
+/// The reason why the credentials of a bucket are rotated
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RotationTrigger {
+    /// The rotation annotation of the bucket changed
+    Requested,
+    /// The rotation interval of the bucket elapsed
+    Scheduled,
+}
+
+impl Display for RotationTrigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RotationTrigger::Requested => write!(f, "on demand"),
+            RotationTrigger::Scheduled => write!(f, "as scheduled"),
+        }
+    }
+}
+
+/// Get the value of the rotation annotation of a bucket, if any
+pub fn rotation_request(b: &MinioBucket) -> Option<&String> {
+    b.annotations().get(ROTATE_CREDENTIALS_ANNOTATION)
+}
+
+/// Get how long the previous credentials of a bucket remain valid after a rotation
+pub fn grace_period(b: &MinioBucket) -> Duration {
+    b.spec
+        .rotation
+        .as_ref()
+        .map(|r| r.grace_period())
+        .unwrap_or(DEFAULT_ROTATION_GRACE_PERIOD)
+}
+
+/// Get the time of the next scheduled rotation of the credentials of a bucket. The schedule
+/// starts with the creation of the credentials, unless they were already rotated
+fn next_rotation(
+    b: &MinioBucket,
+    status: &MinioBucketStatus,
+    credentials_created: Option<&Time>,
+) -> Option<Timestamp> {
+    let interval = b.spec.rotation.as_ref()?.interval()?;
+    let last = status.last_rotation_time.as_ref().or(credentials_created)?;
+    last.0.checked_add(interval).ok()
+}
+
+/// Check if the credentials of a bucket must be rotated
+pub fn rotation_trigger(
+    b: &MinioBucket,
+    status: &MinioBucketStatus,
+    credentials_created: Option<&Time>,
+    now: Timestamp,
+) -> Option<RotationTrigger> {
+    if rotation_request(b).is_some_and(|r| status.rotation_request.as_ref() != Some(r)) {
+        return Some(RotationTrigger::Requested);
+    }
+
+    next_rotation(b, status, credentials_created)
+        .filter(|next| *next <= now)
+        .map(|_| RotationTrigger::Scheduled)
+}
+
+/// Get the previous users of a bucket whose grace period is over
+pub fn expired_users(status: &MinioBucketStatus, now: Timestamp) -> Vec<&RetiredUser> {
+    status
+        .retired_users
+        .iter()
+        .filter(|u| u.expiration_time.0 <= now)
+        .collect()
+}
+
+/// Get the delay before the next scheduled rotation of the credentials of a bucket or the
+/// removal of one of its previous users, if any
+pub fn next_rotation_delay(
+    b: &MinioBucket,
+    status: &MinioBucketStatus,
+    credentials_created: Option<&Time>,
+    now: Timestamp,
+) -> Option<Duration> {
+    next_rotation(b, status, credentials_created)
+        .into_iter()
+        .chain(status.retired_users.iter().map(|u| u.expiration_time.0))
+        .map(|t| Duration::try_from(t.duration_since(now)).unwrap_or_default())
+        .min()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use k8s_openapi::jiff::Timestamp;
+
+    use crate::constants::ROTATE_CREDENTIALS_ANNOTATION;
+    use crate::crd::{CredentialsRotation, MinioBucket, MinioBucketStatus, RetiredUser};
+    use crate::rotation::{expired_users, next_rotation_delay, rotation_trigger, RotationTrigger};
+
+    fn test_bucket(interval_secs: Option<u64>) -> MinioBucket {
+        let mut b = MinioBucket::new("bucket", Default::default());
+        b.spec.rotation = Some(CredentialsRotation {
+            interval_secs,
+            grace_period_secs: None,
+        });
+        b
+    }
+
+    fn at(secs: i64) -> Timestamp {
+        Timestamp::from_second(secs).unwrap()
+    }
+
+    #[test]
+    fn scheduled_rotation() {
+        let b = test_bucket(Some(100));
+        let created = Time(at(1000));
+        let mut status = MinioBucketStatus::default();
+
+        assert_eq!(
+            rotation_trigger(&b, &status, Some(&created), at(1099)),
+            None
+        );
+        assert_eq!(
+            rotation_trigger(&b, &status, Some(&created), at(1100)),
+            Some(RotationTrigger::Scheduled)
+        );
+        assert_eq!(rotation_trigger(&b, &status, None, at(1100)), None);
+        assert_eq!(
+            next_rotation_delay(&b, &status, Some(&created), at(1040)),
+            Some(Duration::from_secs(60))
+        );
+
+        status.last_rotation_time = Some(Time(at(1100)));
+        assert_eq!(
+            rotation_trigger(&b, &status, Some(&created), at(1150)),
+            None
+        );
+        assert_eq!(
+            next_rotation_delay(&b, &status, Some(&created), at(1150)),
+            Some(Duration::from_secs(50))
+        );
+
+        let b = test_bucket(None);
+        assert_eq!(
+            rotation_trigger(&b, &status, Some(&created), at(5000)),
+            None
+        );
+        assert_eq!(
+            next_rotation_delay(&b, &status, Some(&created), at(5000)),
+            None
+        );
+    }
+
+    #[test]
+    fn requested_rotation() {
+        let mut b = test_bucket(None);
+        let mut status = MinioBucketStatus::default();
+        b.metadata.annotations = Some(BTreeMap::from([(
+            ROTATE_CREDENTIALS_ANNOTATION.to_string(),
+            "1".to_string(),
+        )]));
+
+        assert_eq!(
+            rotation_trigger(&b, &status, None, at(0)),
+            Some(RotationTrigger::Requested)
+        );
+        status.rotation_request = Some("1".to_string());
+        assert_eq!(rotation_trigger(&b, &status, None, at(0)), None);
+    }
+
+    #[test]
+    fn expire_retired_users() {
+        let b = test_bucket(None);
+        let status = MinioBucketStatus {
+            retired_users: vec![
+                RetiredUser {
+                    username: "old".to_string(),
+                    expiration_time: Time(at(100)),
+                },
+                RetiredUser {
+                    username: "recent".to_string(),
+                    expiration_time: Time(at(200)),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let expired = expired_users(&status, at(150));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].username, "old");
+        assert_eq!(
+            next_rotation_delay(&b, &status, None, at(150)),
+            Some(Duration::ZERO)
+        );
+    }
+}
@@ -1,15 +1,23 @@
-use k8s_openapi::api::core::v1::Secret;
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
-use kube::api::PostParams;
-use kube::Api;
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
+use k8s_openapi::ByteString;
+use kube::api::{DeleteParams, PostParams, Preconditions};
+use kube::{Api, ResourceExt};
+
+use crate::constants::{SECRET_CREATED_BY_LABEL, SECRET_CREATED_BY_VALUE};
 use std::collections::BTreeMap;
 
+/// The type of secrets that contain arbitrary data
+const SECRET_TYPE_OPAQUE: &str = "Opaque";
+
 #[derive(thiserror::Error, Debug)]
 enum SecretError {
     #[error("Secret has no data!")]
     MissingData,
     #[error("The key '{0}' is not present in the secret!")]
     MissingKey(String),
+    #[error("The key '{0}' is not present in the config map!")]
+    NoConfigMapKey(String),
 }
 
 /// Attempt to read a value contained in a secret. Returns an error in case
@@ -24,41 +32,241 @@ pub fn read_secret_str(s: &Secret, key: &str) -> anyhow::Result<String> {
     Ok(String::from_utf8(value.0.clone())?)
 }
 
+/// Attempt to read a value contained in a config map, either as text or as binary data
+pub fn read_config_map_str(c: &ConfigMap, key: &str) -> anyhow::Result<String> {
+    if let Some(value) = c.data.as_ref().and_then(|d| d.get(key)) {
+        return Ok(value.clone());
+    }
+
+    let value = c
+        .binary_data
+        .as_ref()
+        .and_then(|d| d.get(key))
+        .ok_or(SecretError::NoConfigMapKey(key.to_string()))?;
+    Ok(String::from_utf8(value.0.clone())?)
+}
+
+/// Check if a secret has been created by this operator
+pub fn is_managed_secret(s: &Secret) -> bool {
+    s.metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(SECRET_CREATED_BY_LABEL))
+        .map(|v| v == SECRET_CREATED_BY_VALUE)
+        .unwrap_or(false)
+}
+
+/// The settings of a secret managed by the operator, besides its content
+#[derive(Debug, Default, Clone)]
+pub struct SecretOptions {
+    /// Labels of the secret, besides the label that marks managed secrets
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+    /// The type of the secret, `Opaque` by default
+    pub type_: Option<String>,
+    pub immutable: bool,
+    /// The controller of the secret, which is deleted along with it
+    pub owner: Option<OwnerReference>,
+}
+
+impl SecretOptions {
+    fn secret_type(&self) -> &str {
+        self.type_.as_deref().unwrap_or(SECRET_TYPE_OPAQUE)
+    }
+}
+
 /// Create a secret consisting only of string key / value pairs
 pub async fn create_secret(
     secrets: &Api<Secret>,
     name: &str,
     values: BTreeMap<String, String>,
+    options: &SecretOptions,
 ) -> anyhow::Result<Secret> {
+    let mut labels = options.labels.clone();
+    labels.insert(
+        SECRET_CREATED_BY_LABEL.to_string(),
+        SECRET_CREATED_BY_VALUE.to_string(),
+    );
+
     Ok(secrets
         .create(
             &PostParams::default(),
             &Secret {
                 data: None,
-                immutable: None,
+                immutable: options.immutable.then_some(true),
                 metadata: ObjectMeta {
-                    annotations: None,
+                    annotations: (!options.annotations.is_empty())
+                        .then(|| options.annotations.clone()),
                     creation_timestamp: None,
                     deletion_grace_period_seconds: None,
                     deletion_timestamp: None,
                     finalizers: None,
                     generate_name: None,
                     generation: None,
-                    labels: Some(BTreeMap::from([(
-                        "created-by".to_string(),
-                        "miniok8sbuckets".to_string(),
-                    )])),
+                    labels: Some(labels),
                     managed_fields: None,
                     name: Some(name.to_string()),
                     namespace: None,
-                    owner_references: None,
+                    owner_references: options.owner.clone().map(|o| vec![o]),
                     resource_version: None,
                     self_link: None,
                     uid: None,
                 },
                 string_data: Some(values),
-                type_: None,
+                type_: options.type_.clone(),
             },
         )
         .await?)
 }
+
+/// Check if a secret contains exactly a set of string key / value pairs
+pub fn secret_data_equals(s: &Secret, values: &BTreeMap<String, String>) -> bool {
+    let data = s.data.clone().unwrap_or_default();
+    data.len() == values.len()
+        && values
+            .iter()
+            .all(|(k, v)| data.get(k).is_some_and(|d| d.0 == v.as_bytes()))
+}
+
+/// Get the owner reference of the controller of a secret, if any
+fn secret_controller(s: &Secret) -> Option<&OwnerReference> {
+    s.owner_references()
+        .iter()
+        .find(|o| o.controller == Some(true))
+}
+
+/// Check if a secret has the settings of a managed secret. Labels and annotations added by
+/// other tools are ignored
+fn secret_options_match(s: &Secret, options: &SecretOptions) -> bool {
+    let contains = |actual: &BTreeMap<String, String>, expected: &BTreeMap<String, String>| {
+        expected.iter().all(|(k, v)| actual.get(k) == Some(v))
+    };
+
+    contains(s.labels(), &options.labels)
+        && contains(s.annotations(), &options.annotations)
+        && s.type_.as_deref().unwrap_or(SECRET_TYPE_OPAQUE) == options.secret_type()
+        && s.immutable.unwrap_or(false) == options.immutable
+        && secret_controller(s) == options.owner.as_ref()
+}
+
+/// Check if a managed secret has the expected content and settings
+pub fn secret_up_to_date(
+    s: &Secret,
+    values: &BTreeMap<String, String>,
+    options: &SecretOptions,
+) -> bool {
+    secret_data_equals(s, values) && secret_options_match(s, options)
+}
+
+/// Replace the content and the settings of a managed secret. Fails if the secret was modified
+/// since it was read. Immutable secrets and secrets of another type can not be updated, and are
+/// created again instead
+pub async fn update_secret(
+    secrets: &Api<Secret>,
+    secret: &Secret,
+    values: BTreeMap<String, String>,
+    options: &SecretOptions,
+) -> anyhow::Result<Secret> {
+    let name = secret.name_any();
+    if secret.immutable == Some(true)
+        || secret.type_.as_deref().unwrap_or(SECRET_TYPE_OPAQUE) != options.secret_type()
+    {
+        secrets
+            .delete(
+                &name,
+                &DeleteParams::default().preconditions(Preconditions {
+                    resource_version: secret.resource_version(),
+                    uid: secret.uid(),
+                }),
+            )
+            .await?;
+        return create_secret(secrets, &name, values, options).await;
+    }
+
+    let mut metadata = secret.metadata.clone();
+    metadata.labels = Some({
+        let mut labels = secret.labels().clone();
+        labels.extend(options.labels.clone());
+        labels
+    });
+    if !options.annotations.is_empty() {
+        let mut annotations = secret.annotations().clone();
+        annotations.extend(options.annotations.clone());
+        metadata.annotations = Some(annotations);
+    }
+    let mut owners = secret.owner_references().to_vec();
+    owners.retain(|o| o.controller != Some(true));
+    owners.extend(options.owner.clone());
+    metadata.owner_references = Some(owners);
+
+    let data = values
+        .into_iter()
+        .map(|(k, v)| (k, ByteString(v.into_bytes())))
+        .collect();
+    Ok(secrets
+        .replace(
+            &name,
+            &PostParams::default(),
+            &Secret {
+                data: Some(data),
+                string_data: None,
+                immutable: options.immutable.then_some(true),
+                metadata,
+                ..secret.clone()
+            },
+        )
+        .await?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+    use k8s_openapi::ByteString;
+
+    use crate::secrets::{secret_up_to_date, SecretOptions};
+
+    #[test]
+    fn managed_secret_up_to_date() {
+        let values = BTreeMap::from([("accessKey".to_string(), "user".to_string())]);
+        let mut options = SecretOptions {
+            labels: BTreeMap::from([("app".to_string(), "backup".to_string())]),
+            ..Default::default()
+        };
+
+        let mut secret = Secret {
+            data: Some(BTreeMap::from([(
+                "accessKey".to_string(),
+                ByteString(b"user".to_vec()),
+            )])),
+            ..Default::default()
+        };
+        assert!(!secret_up_to_date(&secret, &values, &options));
+
+        // Labels added by other tools are ignored
+        secret.metadata.labels = Some(BTreeMap::from([
+            ("app".to_string(), "backup".to_string()),
+            ("other".to_string(), "value".to_string()),
+        ]));
+        secret.type_ = Some("Opaque".to_string());
+        assert!(secret_up_to_date(&secret, &values, &options));
+
+        options.owner = Some(OwnerReference {
+            controller: Some(true),
+            uid: "uid".to_string(),
+            ..Default::default()
+        });
+        assert!(!secret_up_to_date(&secret, &values, &options));
+        secret.metadata.owner_references = options.owner.clone().map(|o| vec![o]);
+        assert!(secret_up_to_date(&secret, &values, &options));
+
+        options.immutable = true;
+        assert!(!secret_up_to_date(&secret, &values, &options));
+
+        let values = BTreeMap::from([("accessKey".to_string(), "other".to_string())]);
+        secret.immutable = Some(true);
+        assert!(!secret_up_to_date(&secret, &values, &options));
+    }
+}
@@ -0,0 +1,154 @@
+//! # Label selectors
+//!
+//! Used to check which namespaces are allowed to use a ClusterMinioInstance
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Namespace;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use kube::core::{ParseExpressionError, Selector, SelectorExt};
+
+use crate::crd::ClusterMinioInstanceSpec;
+
+/// Check if a set of labels matches a label selector. Invalid selectors are reported as
+/// errors rather than matching nothing
+pub fn selector_matches(
+    selector: &LabelSelector,
+    labels: &BTreeMap<String, String>,
+) -> Result<bool, ParseExpressionError> {
+    Ok(Selector::try_from(selector.clone())?.matches(labels))
+}
+
+/// Check if buckets of a namespace are allowed to use a ClusterMinioInstance. Namespaces
+/// must either be listed in the allowed namespaces, or match the namespace selector
+pub fn namespace_allowed(
+    spec: &ClusterMinioInstanceSpec,
+    ns: &Namespace,
+) -> Result<bool, ParseExpressionError> {
+    let name = ns.metadata.name.as_deref().unwrap_or_default();
+    if spec.allowed_namespaces.iter().any(|n| n == name) {
+        return Ok(true);
+    }
+
+    match &spec.namespace_selector {
+        Some(selector) => selector_matches(
+            selector,
+            ns.metadata.labels.as_ref().unwrap_or(&BTreeMap::new()),
+        ),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::Namespace;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{
+        LabelSelector, LabelSelectorRequirement, ObjectMeta,
+    };
+
+    use crate::crd::ClusterMinioInstanceSpec;
+    use crate::selectors::{namespace_allowed, selector_matches};
+
+    fn labels(l: &[(&str, &str)]) -> BTreeMap<String, String> {
+        l.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn namespace(name: &str, l: &[(&str, &str)]) -> Namespace {
+        Namespace {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                labels: Some(labels(l)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn requirement(key: &str, operator: &str, values: &[&str]) -> LabelSelectorRequirement {
+        LabelSelectorRequirement {
+            key: key.to_string(),
+            operator: operator.to_string(),
+            values: Some(values.iter().map(|v| v.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn match_labels() {
+        let selector = LabelSelector {
+            match_labels: Some(labels(&[("team", "a")])),
+            match_expressions: None,
+        };
+        assert!(selector_matches(&selector, &labels(&[("team", "a"), ("env", "prod")])).unwrap());
+        assert!(!selector_matches(&selector, &labels(&[("team", "b")])).unwrap());
+        assert!(!selector_matches(&selector, &labels(&[])).unwrap());
+    }
+
+    #[test]
+    fn match_expressions() {
+        let selector = LabelSelector {
+            match_labels: None,
+            match_expressions: Some(vec![
+                requirement("env", "In", &["prod", "staging"]),
+                requirement("restricted", "DoesNotExist", &[]),
+            ]),
+        };
+        assert!(selector_matches(&selector, &labels(&[("env", "prod")])).unwrap());
+        assert!(!selector_matches(&selector, &labels(&[("env", "dev")])).unwrap());
+        assert!(!selector_matches(
+            &selector,
+            &labels(&[("env", "prod"), ("restricted", "true")])
+        )
+        .unwrap());
+
+        let selector = LabelSelector {
+            match_labels: None,
+            match_expressions: Some(vec![
+                requirement("env", "NotIn", &["prod"]),
+                requirement("team", "Exists", &[]),
+            ]),
+        };
+        assert!(selector_matches(&selector, &labels(&[("env", "dev"), ("team", "a")])).unwrap());
+        assert!(!selector_matches(&selector, &labels(&[("env", "dev")])).unwrap());
+    }
+
+    #[test]
+    fn invalid_selector() {
+        let selector = LabelSelector {
+            match_labels: None,
+            match_expressions: Some(vec![requirement("env", "Unknown", &["prod"])]),
+        };
+        assert!(selector_matches(&selector, &labels(&[("env", "prod")])).is_err());
+
+        let spec = ClusterMinioInstanceSpec {
+            namespace_selector: Some(selector),
+            ..Default::default()
+        };
+        assert!(namespace_allowed(&spec, &namespace("team-a", &[])).is_err());
+    }
+
+    #[test]
+    fn allowed_namespaces() {
+        let spec = ClusterMinioInstanceSpec {
+            allowed_namespaces: vec!["team-a".to_string()],
+            namespace_selector: Some(LabelSelector {
+                match_labels: Some(labels(&[("minio", "allowed")])),
+                match_expressions: None,
+            }),
+            ..Default::default()
+        };
+
+        assert!(namespace_allowed(&spec, &namespace("team-a", &[])).unwrap());
+        assert!(namespace_allowed(&spec, &namespace("team-b", &[("minio", "allowed")])).unwrap());
+        assert!(!namespace_allowed(&spec, &namespace("team-c", &[])).unwrap());
+    }
+
+    #[test]
+    fn no_namespace_allowed_by_default() {
+        let spec = ClusterMinioInstanceSpec::default();
+        assert!(!namespace_allowed(&spec, &namespace("default", &[])).unwrap());
+    }
+}
@@ -0,0 +1,242 @@
+//! # Workloads restart
+//!
+//! Restarts the workloads that consume the secret of a bucket, once the operator rewrote it
+
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, StatefulSet};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::jiff::Timestamp;
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{ListParams, Patch, PatchParams};
+use kube::core::Selector;
+use kube::{Api, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+
+use crate::constants::RESTARTED_AT_ANNOTATION;
+use crate::controller::{namespaced_api, Context};
+use crate::crd::{MinioBucket, RestartWorkloads, WorkloadKind};
+
+#[derive(thiserror::Error, Debug)]
+enum WorkloadsError {
+    #[error("The selector of restartOnSecretChange is empty, and would select every workload!")]
+    EmptySelector,
+}
+
+/// The outcome of the restart of the workloads that consume the secret of a bucket
+#[derive(Debug, Default)]
+pub struct RestartedWorkloads {
+    pub restarted: Vec<String>,
+    /// The workloads that could not be restarted or listed, along with the error
+    pub failures: Vec<String>,
+}
+
+/// Restart the workloads that consume the secret of a bucket, by updating an annotation of
+/// their pod template. A failure does not prevent the other workloads from being restarted,
+/// and the referenced workloads that do not exist are skipped. Nothing is restarted if the
+/// selector is invalid or empty
+pub async fn restart_workloads(b: &MinioBucket, ctx: &Context) -> RestartedWorkloads {
+    let at = Timestamp::now().to_string();
+    let mut out = RestartedWorkloads::default();
+    if let Some(Err(e)) = b
+        .spec
+        .restart_on_secret_change
+        .selector
+        .as_ref()
+        .map(selector_params)
+    {
+        out.failures.push(format!("selector: {e}"));
+        return out;
+    }
+    restart_kind::<Deployment>(b, ctx, WorkloadKind::Deployment, &at, &mut out).await;
+    restart_kind::<StatefulSet>(b, ctx, WorkloadKind::StatefulSet, &at, &mut out).await;
+    restart_kind::<DaemonSet>(b, ctx, WorkloadKind::DaemonSet, &at, &mut out).await;
+    out
+}
+
+/// Restart the workloads of a kind that consume the secret of a bucket
+async fn restart_kind<K>(
+    b: &MinioBucket,
+    ctx: &Context,
+    kind: WorkloadKind,
+    restarted_at: &str,
+    out: &mut RestartedWorkloads,
+) where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>
+        + Clone
+        + DeserializeOwned
+        + Debug,
+{
+    let targets = &b.spec.restart_on_secret_change;
+    let api: Api<K> = namespaced_api(&ctx.client, b);
+    // The selector is evaluated by the API server, to only retrieve the selected workloads
+    let listed = match &targets.selector {
+        Some(selector) => match list_selected::<K>(&api, selector).await {
+            Ok(listed) => listed,
+            Err(e) => {
+                out.failures.push(format!("{kind:?} list: {e}"));
+                vec![]
+            }
+        },
+        None => vec![],
+    };
+
+    let patch = serde_json::json!({
+        "spec": {
+            "template": {
+                "metadata": {
+                    "annotations": { RESTARTED_AT_ANNOTATION: restarted_at }
+                }
+            }
+        }
+    });
+
+    for name in selected_workloads(targets, kind, &listed) {
+        log::info!(
+            "Restart {kind:?} {name} which consumes the secret {}",
+            b.spec.secret
+        );
+        match api
+            .patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+        {
+            Ok(_) => out.restarted.push(format!("{kind:?}/{name}")),
+            Err(kube::Error::Api(e)) if e.is_not_found() => {
+                log::warn!("{kind:?} {name} does not exist, it can not be restarted")
+            }
+            Err(e) => {
+                log::error!("Failed to restart {kind:?} {name}: {e}");
+                out.failures.push(format!("{kind:?}/{name}: {e}"));
+            }
+        }
+    }
+}
+
+/// List the workloads of a kind whose labels match a selector
+async fn list_selected<K>(api: &Api<K>, selector: &LabelSelector) -> anyhow::Result<Vec<K>>
+where
+    K: Resource + Clone + DeserializeOwned + Debug,
+{
+    Ok(api.list(&selector_params(selector)?).await?.items)
+}
+
+/// Get the parameters of a request that lists the objects whose labels match a selector.
+/// Empty selectors are refused
+fn selector_params(selector: &LabelSelector) -> anyhow::Result<ListParams> {
+    let selector = Selector::try_from(selector.clone())?;
+    if selector.selects_all() {
+        return Err(WorkloadsError::EmptySelector.into());
+    }
+    Ok(ListParams::default().labels_from(&selector))
+}
+
+/// Get the names of the workloads of a kind that are referenced, or that were selected by
+/// their labels
+fn selected_workloads<K: ResourceExt>(
+    targets: &RestartWorkloads,
+    kind: WorkloadKind,
+    selected: &[K],
+) -> BTreeSet<String> {
+    targets
+        .workloads
+        .iter()
+        .filter(|w| w.kind == kind)
+        .map(|w| w.name.clone())
+        .chain(selected.iter().map(|w| w.name_any()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use k8s_openapi::api::apps::v1::Deployment;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{
+        LabelSelector, LabelSelectorRequirement, ObjectMeta,
+    };
+
+    use crate::crd::{RestartWorkloads, WorkloadKind, WorkloadRef};
+    use crate::workloads::{selected_workloads, selector_params};
+
+    fn deployment(name: &str) -> Deployment {
+        Deployment {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn select_workloads() {
+        let targets = RestartWorkloads {
+            workloads: vec![
+                WorkloadRef {
+                    kind: WorkloadKind::Deployment,
+                    name: "api".to_string(),
+                },
+                WorkloadRef {
+                    kind: WorkloadKind::StatefulSet,
+                    name: "db".to_string(),
+                },
+            ],
+            selector: None,
+        };
+
+        assert_eq!(
+            selected_workloads(&targets, WorkloadKind::Deployment, &[] as &[Deployment]),
+            BTreeSet::from(["api".to_string()])
+        );
+        assert_eq!(
+            selected_workloads(
+                &targets,
+                WorkloadKind::Deployment,
+                &[deployment("api"), deployment("worker")]
+            ),
+            BTreeSet::from(["api".to_string(), "worker".to_string()])
+        );
+        assert_eq!(
+            selected_workloads(&targets, WorkloadKind::DaemonSet, &[] as &[Deployment]),
+            BTreeSet::new()
+        );
+    }
+
+    #[test]
+    fn server_side_selector() {
+        let selector = LabelSelector {
+            match_labels: Some(BTreeMap::from([("app".to_string(), "backup".to_string())])),
+            match_expressions: Some(vec![LabelSelectorRequirement {
+                key: "tier".to_string(),
+                operator: "In".to_string(),
+                values: Some(vec!["batch".to_string()]),
+            }]),
+        };
+        let params = selector_params(&selector).unwrap();
+        let labels = params.label_selector.unwrap();
+        assert!(labels.contains("app=backup"), "{labels}");
+        assert!(labels.contains("tier in (batch)"), "{labels}");
+
+        let invalid = LabelSelector {
+            match_expressions: Some(vec![LabelSelectorRequirement {
+                key: "tier".to_string(),
+                operator: "Unknown".to_string(),
+                values: None,
+            }]),
+            ..Default::default()
+        };
+        assert!(selector_params(&invalid).is_err());
+    }
+
+    #[test]
+    fn reject_empty_selector() {
+        assert!(selector_params(&LabelSelector::default()).is_err());
+
+        let empty = LabelSelector {
+            match_labels: Some(BTreeMap::new()),
+            match_expressions: Some(vec![]),
+        };
+        assert!(selector_params(&empty).is_err());
+    }
+}